@@ -0,0 +1,55 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use order_book_parser::{
+    OrderBook, parse_order_book, parse_order_book_fast, parse_order_book_into,
+};
+use std::hint::black_box;
+
+fn snapshots(count: usize) -> Vec<String> {
+    (0..count)
+        .map(|i| {
+            let bid = 100 + (i % 50);
+            format!(
+                "BIDS:{}.0,10|{}.5,20|{}.0,30;ASKS:{}.0,5|{}.5,10|{}.0,15",
+                bid,
+                bid - 1,
+                bid - 1,
+                bid + 1,
+                bid + 1,
+                bid + 2
+            )
+        })
+        .collect()
+}
+
+fn bench_parse(c: &mut Criterion) {
+    let inputs = snapshots(10_000);
+
+    c.bench_function("parse_order_book 10k snapshots", |b| {
+        b.iter(|| {
+            for input in &inputs {
+                black_box(parse_order_book(black_box(input), None).unwrap());
+            }
+        })
+    });
+
+    c.bench_function("parse_order_book_fast 10k snapshots", |b| {
+        b.iter(|| {
+            for input in &inputs {
+                black_box(parse_order_book_fast(black_box(input)).unwrap());
+            }
+        })
+    });
+
+    c.bench_function("parse_order_book_into 10k snapshots", |b| {
+        let mut book = OrderBook::default();
+        b.iter(|| {
+            for input in &inputs {
+                parse_order_book_into(black_box(input), &mut book, None).unwrap();
+                black_box(&book);
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
@@ -1,7 +1,29 @@
+//! Order book snapshot parsing, validation and analytics.
+//!
+//! With the default `std` feature disabled the crate builds as `no_std` on top
+//! of `alloc`: the data types, the text parser, validation, execution and the
+//! analytics remain available, while IO helpers (readers, gzip, JSON Lines)
+//! require `std`.
+#![cfg_attr(not(feature = "std"), no_std)]
+
+extern crate alloc;
+
+#[cfg(not(feature = "std"))]
+use alloc::{
+    boxed::Box,
+    format,
+    string::{String, ToString},
+    vec::Vec,
+};
+use core::fmt;
+use core::str::FromStr;
 use pest::Parser;
 use pest_derive::Parser;
-use rust_decimal::Decimal;
-use std::fmt;
+use rust_decimal::{Decimal, RoundingStrategy};
+#[cfg(feature = "std")]
+use std::io::Read;
+#[cfg(feature = "json")]
+use std::io::Write;
 use thiserror::Error;
 
 /// The main parser structure for processing Order Book snapshots.
@@ -16,15 +38,17 @@ use thiserror::Error;
 ///
 /// - **`ASCII_DIGIT`**: Matches any single digit from '0' to '9'.
 ///
-/// - **`integer`**: Matches a sequence of one or more digits.
-///   Defined as: `@{ ASCII_DIGIT+ }`
+/// - **`integer`**: Matches a sequence of one or more digits, optionally grouped
+///   with single underscores (e.g., "1_000"). Underscores are stripped before conversion.
+///   Defined as: `@{ ASCII_DIGIT+ ~ ("_" ~ ASCII_DIGIT+)* }`
 ///
 /// - **`number`**: Matches a financial number, which can be an integer or a decimal.
 ///   Defined as: `@{ integer ~ ("." ~ integer)? }`
 ///
-/// - **`level`**: Represents a single price level consisting of a Price and a Quantity.
-///   Format: `price,quantity` (e.g., "100.5,10").
-///   Defined as: `{ number ~ "," ~ number }`
+/// - **`level`**: Represents a single price level consisting of a Price, a Quantity
+///   and an optional order ID for order-by-order (L3) data.
+///   Format: `price,quantity` or `price,quantity,id` (e.g., "100.5,10" or "100.5,10,42").
+///   Defined as: `{ number ~ "," ~ number ~ ("," ~ integer)? }`
 ///
 /// - **`level_list`**: Represents a sequence of levels separated by a pipe `|`.
 ///   Defined as: `{ (level)? ~ ("|" ~ level)* }`
@@ -35,14 +59,23 @@ use thiserror::Error;
 /// - **`asks_side`**: Identifies the Sell side of the order book.
 ///   Format: `ASKS:level_list`
 ///
+/// - **`checksum`**: An optional CRC32 trailer used to detect feed desync.
+///   Format: `|CRC=<value>` (see `OrderBook::crc32_top`).
+///
 /// - **`order_book`**: The root rule that combines both sides.
-///   Format: `bids_side ~ ";" ~ asks_side`
+///   Format: `bids_side ~ ";" ~ asks_side ~ checksum?`, or a single
+///   `bids_side` / `asks_side` (optionally with a checksum) spanning the whole
+///   input, in which case the missing side is empty.
 #[derive(Parser)]
 #[grammar = "grammar.pest"]
 pub struct OrderBookParser;
 
 /// Custom error type for the Order Book library.
+///
+/// The enum is `#[non_exhaustive]` so that new variants can be added without
+/// breaking downstream `match` statements; include a wildcard arm.
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum OrderBookError {
     /// Error propagated from the Pest parser (grammar mismatch).
     #[error("Failed to parse the input string: {0}")]
@@ -50,31 +83,42 @@ pub enum OrderBookError {
 
     /// Error parsing a string into a Decimal number.
     #[error("Failed to parse number: {0}")]
-    DecimalError(#[from] rust_decimal::Error),
+    DecimalError(#[cfg_attr(feature = "std", from)] rust_decimal::Error),
 
     /// Logical error when a required section (like Price or Quantity) is missing.
     #[error("Missing required section: {0}")]
     MissingSection(String),
 
     /// Validation error: Bids are not sorted descending.
+    /// Carries the byte range of the offending level in the input, when known.
     #[error("Bids must be sorted descending (highest first). Found issue at price {0}")]
-    BidsUnsorted(Decimal),
+    BidsUnsorted(Decimal, Option<(usize, usize)>),
 
     /// Validation error: Asks are not sorted ascending.
+    /// Carries the byte range of the offending level in the input, when known.
     #[error("Asks must be sorted ascending (lowest first). Found issue at price {0}")]
-    AsksUnsorted(Decimal),
+    AsksUnsorted(Decimal, Option<(usize, usize)>),
 
     /// Validation error: Duplicate price levels exist.
+    /// Carries the byte range of the repeated level in the input, when known.
     #[error("Duplicate price level found: {0}")]
-    DuplicatePrice(Decimal),
+    DuplicatePrice(Decimal, Option<(usize, usize)>),
 
-    /// Validation error: The best Bid is higher than or equal to the best Ask.
-    #[error("Crossed book detected: Best Bid ({0}) is >= Best Ask ({1})")]
+    /// Validation error: The best Bid is higher than the best Ask.
+    #[error("Crossed book detected: Best Bid ({0}) is > Best Ask ({1})")]
     CrossedBook(Decimal, Decimal),
 
+    /// Validation error: The best Bid equals the best Ask.
+    #[error("Locked book detected: Best Bid equals Best Ask ({0})")]
+    LockedBook(Decimal),
+
     /// Instrument validation: Price is not a multiple of the tick size.
-    #[error("Price {0} is not a multiple of tick size {1}")]
-    InvalidTickSize(Decimal, Decimal),
+    /// Carries the nearest valid price, or `None` if computing it overflows.
+    #[error(
+        "Price {0} is not a multiple of tick size {1}{suggestion}",
+        suggestion = DidYouMean(*.2)
+    )]
+    InvalidTickSize(Decimal, Decimal, Option<Decimal>),
 
     /// Instrument validation: Quantity is below the minimum lot.
     #[error("Quantity {0} is less than minimum lot size {1}")]
@@ -84,9 +128,114 @@ pub enum OrderBookError {
     #[error("Quantity {0} is not a multiple of lot step {1}")]
     InvalidLotStep(Decimal, Decimal),
 
+    /// Validation error: The top level of a side has zero quantity.
+    #[error("Top level at price {0} has zero quantity")]
+    ZeroTopQuantity(Decimal),
+
+    /// Validation error: The number of bid and ask levels differs by more than allowed.
+    #[error("Depth skew exceeds limit: {0} bid levels vs {1} ask levels")]
+    DepthSkew(usize, usize),
+
+    /// Validation error: A price or quantity that must be positive is zero or negative.
+    #[error("Value must be positive, got {0}")]
+    NonPositiveValue(Decimal),
+
+    /// Trading error: Positions on opposite sides cannot be combined.
+    #[error("Cannot merge a {0:?} position with a {1:?} position")]
+    SideMismatch(Side, Side),
+
+    /// Trading error: Attempt to close more than the open position size.
+    #[error("Cannot reduce position by {0}: only {1} is open")]
+    ReduceExceedsPosition(Decimal, Decimal),
+
+    /// Trading error: A position in a portfolio has no exit liquidity to be marked against.
+    #[error("Position #{0} cannot be marked: no liquidity on the exit side")]
+    UnmarkablePosition(usize),
+
     /// Trading error: Not enough liquidity in the book to fill the order.
     #[error("Not enough liquidity to fill order. Requested: {0}, Available: {1}")]
     NotEnoughLiquidity(Decimal, Decimal),
+
+    /// Validation error: A level price lies outside the instrument's
+    /// `min_price`/`max_price` bounds.
+    #[error("Price {0} is outside the instrument's allowed range")]
+    PriceOutOfRange(Decimal),
+
+    /// Validation error: A quantity is below zero.
+    #[error("Quantity must not be negative, got {0}")]
+    NegativeQuantity(Decimal),
+
+    /// Configuration error: An instrument parameter is unusable (e.g. zero, negative or NaN).
+    #[error("Invalid instrument configuration: {0}")]
+    InvalidConfig(String),
+
+    /// Parsing error: Whitespace inside a section while `strict_whitespace` is set.
+    /// Carries the byte offset of the whitespace in the input.
+    #[error("Unexpected whitespace at byte {0}")]
+    UnexpectedWhitespace(usize),
+
+    /// Parsing error: A `%` suffix is misplaced or does not match the
+    /// configured `QuantityFormat`.
+    #[error("Invalid quantity format: {0}")]
+    InvalidQuantityFormat(String),
+
+    /// Parsing error: Unconsumed input follows a complete order book.
+    #[error("Unexpected trailing data after order book: {0:?}")]
+    TrailingData(String),
+
+    /// Error converting a level's order ID into a `u64`.
+    #[error("Invalid order ID: {0}")]
+    InvalidOrderId(String),
+
+    /// Integrity error: The checksum in the input does not match the parsed book.
+    #[error("Checksum mismatch: input declares {0}, book computes {1}")]
+    ChecksumMismatch(u32, u32),
+
+    /// Error converting the checksum trailer into a `u32`.
+    #[error("Invalid checksum value: {0}")]
+    InvalidChecksum(String),
+
+    /// Error reading a fixture produced by `OrderBook::to_fixture`.
+    #[error("Invalid fixture at line {0}: {1}")]
+    InvalidFixture(usize, String),
+
+    /// Error encoding or decoding an order book in its binary representation.
+    #[error("Failed to process binary order book: {0}")]
+    BinaryError(String),
+
+    /// Error reading snapshot data from an input source.
+    #[cfg(feature = "std")]
+    #[error("Failed to read input: {0}")]
+    IoError(#[from] std::io::Error),
+
+    /// Error encoding or decoding an order book as JSON.
+    #[error("Failed to process JSON: {0}")]
+    JsonError(String),
+
+    /// Arithmetic error: A notional computation exceeded the range of `Decimal`.
+    #[error("Arithmetic overflow while computing notional value")]
+    Overflow,
+
+    /// Error in one snapshot of a multi-snapshot input, with its 0-based index.
+    #[error("Snapshot #{0} failed: {1}")]
+    SnapshotError(usize, #[source] Box<OrderBookError>),
+
+    /// Requested behaviour that the book model does not support yet.
+    #[error("Unsupported: {0}")]
+    Unsupported(String),
+
+    /// Catch-all for failures that do not fit any of the other variants.
+    #[error("Internal error: {0}")]
+    Internal(String),
+}
+
+// `rust_decimal::Error` only implements `Error` with `std`, so it cannot be a
+// `#[from]` source without it.
+#[cfg(not(feature = "std"))]
+impl From<rust_decimal::Error> for OrderBookError {
+    fn from(err: rust_decimal::Error) -> Self {
+        OrderBookError::DecimalError(err)
+    }
 }
 
 // Implement manual From to handle the Boxed error
@@ -106,42 +255,396 @@ pub struct InstrumentConfig {
     pub min_lot: Decimal,
     /// Step by which quantity can increase.
     pub lot_step: Decimal,
+    /// Factor applied to every parsed price, for venues quoting integer ticks
+    /// (e.g. `0.01` turns `10050` into `100.50`). `None` leaves prices as written.
+    ///
+    /// Scaling happens after checksum verification and before validation. A
+    /// scaled value outside the range of `Decimal` fails with `Overflow`.
+    pub price_scale: Option<Decimal>,
+    /// Factor applied to every parsed quantity. `None` leaves quantities as written.
+    pub qty_scale: Option<Decimal>,
+    /// Lowest acceptable level price. `None` disables the lower bound.
+    pub min_price: Option<Decimal>,
+    /// Highest acceptable level price. `None` disables the upper bound.
+    pub max_price: Option<Decimal>,
 }
 
 impl InstrumentConfig {
     /// Creates a new configuration from f64 values.
+    ///
+    /// Each value is converted through its shortest decimal representation, so
+    /// `0.0001` becomes exactly `0.0001` rather than the nearest binary float.
     pub fn new(tick_size: f64, min_lot: f64, lot_step: f64) -> Self {
         Self {
-            tick_size: Decimal::from_f64_retain(tick_size).unwrap_or_default(),
-            min_lot: Decimal::from_f64_retain(min_lot).unwrap_or_default(),
-            lot_step: Decimal::from_f64_retain(lot_step).unwrap_or_default(),
+            tick_size: f64_to_decimal(tick_size).unwrap_or_default(),
+            min_lot: f64_to_decimal(min_lot).unwrap_or_default(),
+            lot_step: f64_to_decimal(lot_step).unwrap_or_default(),
+            price_scale: None,
+            qty_scale: None,
+            min_price: None,
+            max_price: None,
+        }
+    }
+
+    /// Creates a new configuration from decimal strings such as `"0.0001"`.
+    ///
+    /// Values are parsed with `Decimal::from_str_exact`, so they are represented
+    /// exactly. Prefer this over the f64 constructors when the specification is
+    /// available as text.
+    ///
+    /// # Errors
+    /// * `DecimalError` - if a value is not a valid decimal number.
+    /// * `InvalidConfig` - if a value is not positive.
+    pub fn from_str_parts(
+        tick_size: &str,
+        min_lot: &str,
+        lot_step: &str,
+    ) -> Result<Self, OrderBookError> {
+        let parse = |name: &str, raw: &str| -> Result<Decimal, OrderBookError> {
+            let value = Decimal::from_str_exact(raw.trim())?;
+            if value <= Decimal::ZERO {
+                return Err(OrderBookError::InvalidConfig(format!(
+                    "{} must be positive, got {}",
+                    name, value
+                )));
+            }
+            Ok(value)
+        };
+        Ok(Self {
+            tick_size: parse("tick_size", tick_size)?,
+            min_lot: parse("min_lot", min_lot)?,
+            lot_step: parse("lot_step", lot_step)?,
+            price_scale: None,
+            qty_scale: None,
+            min_price: None,
+            max_price: None,
+        })
+    }
+
+    /// Creates a new configuration from f64 values, rejecting unusable input.
+    ///
+    /// Unlike `new`, which silently turns NaN or out-of-range values into zero,
+    /// this returns `InvalidConfig` if any value is non-finite or not positive.
+    pub fn try_new(tick_size: f64, min_lot: f64, lot_step: f64) -> Result<Self, OrderBookError> {
+        Ok(Self {
+            tick_size: positive_decimal("tick_size", tick_size)?,
+            min_lot: positive_decimal("min_lot", min_lot)?,
+            lot_step: positive_decimal("lot_step", lot_step)?,
+            price_scale: None,
+            qty_scale: None,
+            min_price: None,
+            max_price: None,
+        })
+    }
+
+    /// Rounds `price` to the nearest multiple of `tick_size`.
+    ///
+    /// Halfway prices round away from zero, matching `round_to_tick` parsing.
+    /// A zero tick size leaves the price unchanged. Returns `None` if the
+    /// rounding overflows `Decimal`.
+    pub fn nearest_tick(&self, price: Decimal) -> Option<Decimal> {
+        snap_to_step(price, self.tick_size)
+    }
+}
+
+fn positive_decimal(name: &str, value: f64) -> Result<Decimal, OrderBookError> {
+    if !value.is_finite() || value <= 0.0 {
+        return Err(OrderBookError::InvalidConfig(format!(
+            "{} must be a positive finite number, got {}",
+            name, value
+        )));
+    }
+    f64_to_decimal(value).ok_or_else(|| {
+        OrderBookError::InvalidConfig(format!("{} is out of range: {}", name, value))
+    })
+}
+
+/// Converts an f64 via its shortest round-trip decimal string, avoiding the
+/// binary noise `Decimal::from_f64_retain` keeps (e.g. for `0.1`).
+fn f64_to_decimal(value: f64) -> Option<Decimal> {
+    Decimal::from_str_exact(&value.to_string()).ok()
+}
+
+/// Optional logical checks applied on top of the standard book validation.
+///
+/// Every check is disabled by default, so `ValidationOptions::default()`
+/// preserves the behavior of `parse_order_book`.
+#[derive(Debug, Clone, Default)]
+pub struct ValidationOptions {
+    /// Reject books whose best bid or best ask level has zero quantity.
+    pub forbid_zero_top: bool,
+    /// Maximum allowed difference between the number of bid and ask levels.
+    pub max_depth_skew: Option<usize>,
+}
+
+/// Number of levels per side covered by the `|CRC=<value>` input checksum.
+pub const CHECKSUM_DEPTH: usize = 10;
+
+/// Order of the two numeric fields in each input level.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LevelOrder {
+    /// `price,quantity` — the canonical format.
+    #[default]
+    PriceFirst,
+    /// `quantity,price`, as used by some partner feeds.
+    QuantityFirst,
+}
+
+/// How quantity tokens in the input are interpreted.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum QuantityFormat {
+    /// Plain quantities; a `%` suffix is rejected.
+    #[default]
+    Absolute,
+    /// Every quantity carries a `%` suffix and is stored as a fraction
+    /// (`25%` becomes `0.25`) until `OrderBook::resolve_percent_quantities`
+    /// scales it by the total depth.
+    Percent,
+}
+
+/// Options controlling how `parse_order_book_with_options` processes input.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+    /// Additional validation rules applied after parsing.
+    pub validation: ValidationOptions,
+    /// Round each parsed price to the nearest multiple of `tick_size` and each
+    /// quantity to the nearest multiple of `lot_step` before validation.
+    ///
+    /// Has no effect when no `InstrumentConfig` is supplied. A value too large
+    /// to round within the range of `Decimal` fails with `Overflow`.
+    pub round_to_tick: bool,
+    /// Report crossed and locked books as warnings instead of errors.
+    ///
+    /// Warnings are only surfaced by `parse_order_book_with_warnings`.
+    pub crossed_as_warning: bool,
+    /// Report unsorted sides and duplicate prices as warnings instead of errors.
+    ///
+    /// Warnings are only surfaced by `parse_order_book_with_warnings`.
+    pub unsorted_as_warning: bool,
+    /// Accept snapshots where both sides have no levels.
+    ///
+    /// By default such a book is rejected with `MissingSection("Empty book")`
+    /// so that a valid-but-empty snapshot is not confused with a missing feed.
+    pub allow_empty: bool,
+    /// Drop blank lines and whole lines starting with `#` before parsing.
+    ///
+    /// Only full-line comments are recognized (leading whitespace allowed).
+    /// Byte spans in errors then refer to the text with those lines removed.
+    pub strip_comments: bool,
+    /// Merge adjacent levels that share a price into one level, summing their
+    /// quantities, instead of failing with `DuplicatePrice`.
+    ///
+    /// A merged level loses its order ID. Duplicates that are not adjacent are
+    /// still reported as unsorted. A merged quantity outside the range of
+    /// `Decimal` fails with `Overflow`.
+    pub aggregate_duplicates: bool,
+    /// Reject whitespace inside a section (`BIDS : 100 , 5`), which usually
+    /// signals a corrupted feed. Whitespace between the sections and around
+    /// the whole snapshot is still allowed.
+    pub strict_whitespace: bool,
+    /// How quantity tokens are interpreted.
+    ///
+    /// With `QuantityFormat::Percent`, instrument lot rules would see the
+    /// fractions, so parse without a config and call
+    /// `OrderBook::validate_instrument` after resolving the quantities.
+    pub quantity_format: QuantityFormat,
+    /// Which of the two level fields holds the price. Swapped fields are put
+    /// back in place before any validation runs.
+    pub level_order: LevelOrder,
+}
+
+/// A non-fatal data quality issue found while parsing.
+///
+/// Produced instead of the matching `OrderBookError` when the corresponding
+/// `ParseOptions` flag downgrades it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OrderBookWarning {
+    /// The best Bid is higher than the best Ask: `(bid, ask)`.
+    CrossedBook(Decimal, Decimal),
+    /// The best Bid equals the best Ask.
+    LockedBook(Decimal),
+    /// Bids are not sorted descending at the given price.
+    BidsUnsorted(Decimal),
+    /// Asks are not sorted ascending at the given price.
+    AsksUnsorted(Decimal),
+    /// The same price appears on consecutive levels.
+    DuplicatePrice(Decimal),
+}
+
+impl fmt::Display for OrderBookWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OrderBookWarning::CrossedBook(bid, ask) => {
+                write!(
+                    f,
+                    "Crossed book: Best Bid ({}) is > Best Ask ({})",
+                    bid, ask
+                )
+            }
+            OrderBookWarning::LockedBook(price) => {
+                write!(f, "Locked book: Best Bid equals Best Ask ({})", price)
+            }
+            OrderBookWarning::BidsUnsorted(price) => {
+                write!(f, "Bids not sorted descending at price {}", price)
+            }
+            OrderBookWarning::AsksUnsorted(price) => {
+                write!(f, "Asks not sorted ascending at price {}", price)
+            }
+            OrderBookWarning::DuplicatePrice(price) => {
+                write!(f, "Duplicate price level: {}", price)
+            }
         }
     }
 }
 
 /// Represents the side of a trade (Buy or Sell).
-#[derive(Debug, PartialEq, Clone, Copy)]
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Side {
     Buy,
     Sell,
 }
 
+impl Side {
+    /// Returns the other side: `Buy` for `Sell` and vice versa.
+    pub fn opposite(self) -> Side {
+        match self {
+            Side::Buy => Side::Sell,
+            Side::Sell => Side::Buy,
+        }
+    }
+}
+
+impl fmt::Display for Side {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Side::Buy => write!(f, "Buy"),
+            Side::Sell => write!(f, "Sell"),
+        }
+    }
+}
+
 /// Represents a single price level in the order book (Price and Quantity).
-#[derive(Debug, PartialEq, Clone)]
+#[derive(Debug, PartialEq, Eq, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Level {
     pub price: Decimal,
     pub quantity: Decimal,
+    /// Whether the level can actually be traded against. Indicative quotes are
+    /// marked `false` and are ignored by order execution, best-price lookups,
+    /// the book analytics (VWAP, fair value, depth and concentration measures)
+    /// and the crossed/locked book checks.
+    #[cfg_attr(feature = "serde", serde(default = "default_tradeable"))]
+    pub tradeable: bool,
+    /// Optional order identifier for order-by-order (L3) data.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub id: Option<u64>,
+    /// Optional time the level was last updated, in caller-defined units
+    /// (e.g. Unix milliseconds).
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub timestamp: Option<i64>,
+}
+
+impl Level {
+    /// Creates a tradeable level without an order ID, validating its values.
+    ///
+    /// # Errors
+    /// * `NonPositiveValue` - if `price` or `quantity` is zero or negative.
+    pub fn new(price: Decimal, quantity: Decimal) -> Result<Level, OrderBookError> {
+        for value in [price, quantity] {
+            if value <= Decimal::ZERO {
+                return Err(OrderBookError::NonPositiveValue(value));
+            }
+        }
+        Ok(Level::new_unchecked(price, quantity))
+    }
+
+    /// Creates a tradeable level without an order ID and without any checks.
+    ///
+    /// Intended for callers that have already validated the values, such as
+    /// the parser after the grammar has accepted them.
+    pub fn new_unchecked(price: Decimal, quantity: Decimal) -> Level {
+        Level {
+            price,
+            quantity,
+            tradeable: true,
+            id: None,
+            timestamp: None,
+        }
+    }
+}
+
+#[cfg(feature = "serde")]
+fn default_tradeable() -> bool {
+    true
 }
 
 /// Represents the full Order Book containing Bids and Asks.
-#[derive(Debug, Default, Clone)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct OrderBook {
     pub bids: Vec<Level>,
     pub asks: Vec<Level>,
+    /// Instrument the book belongs to, when known. Parsing a single snapshot
+    /// leaves it `None`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub symbol: Option<String>,
+    /// Currency the prices are denominated in, when known.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub currency: Option<String>,
+}
+
+/// A single fill produced by `OrderBook::match_incoming`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Trade {
+    pub price: Decimal,
+    pub quantity: Decimal,
+    /// Side of the resting order that provided the liquidity.
+    pub maker_side: Side,
+}
+
+/// Best bid and best ask of a book, as returned by `OrderBook::top_of_book`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TopOfBook {
+    pub bid_price: Decimal,
+    pub bid_qty: Decimal,
+    pub ask_price: Decimal,
+    pub ask_qty: Decimal,
+}
+
+/// Which touch level `OrderBook::uncross_with` removes while the book is crossed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum UncrossStrategy {
+    /// Remove whichever of the best bid and best ask has the smaller quantity
+    /// (the best bid on a tie), treating the thinner quote as the stale one.
+    #[default]
+    DropSmaller,
+    /// Always remove the best bid, trusting the asks.
+    DropBids,
+    /// Always remove the best ask, trusting the bids.
+    DropAsks,
+}
+
+/// How a market order's quantity is allocated among resting orders at the same price.
+///
+/// Price priority always applies first: better-priced levels are consumed
+/// before worse ones.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MatchPolicy {
+    /// Time priority: orders at a price are filled in arrival order.
+    #[default]
+    Fifo,
+    /// Each order at a price receives a share proportional to its size.
+    /// Not supported yet: levels are aggregated, so there are no individual
+    /// resting orders to share a fill between.
+    ProRata,
 }
 
 /// Represents an open position resulting from a trade execution.
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Position {
     pub side: Side,
     pub quantity: Decimal,
@@ -155,198 +658,2589 @@ impl Position {
     /// * **Long (Buy)** positions close at the best available **Bid** price.
     /// * **Short (Sell)** positions close at the best available **Ask** price.
     ///
-    /// Returns `None` if there is no liquidity to calculate the exit price.
+    /// Returns `None` if there is no liquidity to calculate the exit price or
+    /// the PnL overflows `Decimal`.
     pub fn calculate_pnl(&self, book: &OrderBook) -> Option<Decimal> {
+        self.pnl_at(self.touch_exit_price(book)?)
+    }
+
+    /// Best price the position would close at: the best Bid for a long
+    /// (Buy) position, the best Ask for a short (Sell) one.
+    fn touch_exit_price(&self, book: &OrderBook) -> Option<Decimal> {
         match self.side {
-            Side::Buy => {
-                // Long: We sell at the Best Bid
-                let best_bid = book.bids.first()?.price;
-                Some((best_bid - self.entry_price) * self.quantity)
-            }
-            Side::Sell => {
-                // Short: We buy back at the Best Ask
-                let best_ask = book.asks.first()?.price;
-                Some((self.entry_price - best_ask) * self.quantity)
-            }
+            Side::Buy => book.best_bid(false).map(|level| level.price),
+            Side::Sell => book.best_ask(false).map(|level| level.price),
         }
     }
-}
 
-impl OrderBook {
-    /// Executes a Market Order with Partial Fill logic (IOC).
-    ///
-    /// This method mutates the order book by consuming liquidity from the opposite side.
+    /// PnL of closing the whole position at `exit`, or `None` on overflow.
+    fn pnl_at(&self, exit: Decimal) -> Option<Decimal> {
+        let per_unit = match self.side {
+            Side::Buy => exit.checked_sub(self.entry_price)?,
+            Side::Sell => self.entry_price.checked_sub(exit)?,
+        };
+        per_unit.checked_mul(self.quantity)
+    }
+
+    /// Calculates PnL as if the whole position were closed with a market order now.
     ///
-    /// # Arguments
-    /// * `side` - The direction of the trade (Buy or Sell).
-    /// * `quantity` - The amount to trade.
+    /// The exit is simulated without mutating the book: a long position sells
+    /// into the Bids, a short position buys from the Asks, walking tradeable
+    /// levels and marking at the resulting VWAP. Unlike `calculate_pnl`, this
+    /// accounts for the slippage of exiting a size larger than the touch.
     ///
-    /// # Returns
-    /// * `Ok(Position)` - The resulting position with the weighted average entry price.
-    /// * `Err(OrderBookError)` - If the order is invalid or book is empty.
-    pub fn execute_market_order(
-        &mut self,
-        side: Side,
-        quantity: Decimal,
-    ) -> Result<Position, OrderBookError> {
-        if quantity <= Decimal::ZERO {
-            return Err(OrderBookError::NotEnoughLiquidity(quantity, Decimal::ZERO));
+    /// Returns `None` if the exit side cannot absorb the full quantity.
+    pub fn liquidation_pnl(&self, book: &OrderBook) -> Option<Decimal> {
+        let levels = match self.side {
+            Side::Buy => &book.bids,
+            Side::Sell => &book.asks,
+        };
+        let (exit_price, filled) = simulate_fill(levels, self.quantity)?;
+        if filled < self.quantity {
+            return None;
         }
+        self.pnl_at(exit_price)
+    }
 
-        let levels = match side {
-            Side::Buy => &mut self.asks,
-            Side::Sell => &mut self.bids,
-        };
+    /// Like `calculate_pnl`, but marks at an exit price snapped to the instrument tick.
+    ///
+    /// The best bid (long) or best ask (short) is rounded to the nearest
+    /// multiple of `config.tick_size`, halves away from zero, before the PnL is
+    /// computed. This filters out noise from quotes slightly off the tick grid.
+    pub fn calculate_pnl_with_config(
+        &self,
+        book: &OrderBook,
+        config: &InstrumentConfig,
+    ) -> Option<Decimal> {
+        let exit = snap_to_step(self.touch_exit_price(book)?, config.tick_size)?;
+        self.pnl_at(exit)
+    }
 
-        let mut remaining_qty = quantity;
-        let mut total_cost = Decimal::ZERO;
-        let mut filled_qty = Decimal::ZERO;
+    /// Unrealized PnL rounded to `dp` decimal places.
+    ///
+    /// Uses `Decimal::round_dp` (banker's rounding) on the result of
+    /// `calculate_pnl`, which remains the unrounded primitive.
+    pub fn calculate_pnl_rounded(&self, book: &OrderBook, dp: u32) -> Option<Decimal> {
+        self.calculate_pnl(book).map(|pnl| pnl.round_dp(dp))
+    }
 
-        let mut i = 0;
-        while i < levels.len() && remaining_qty > Decimal::ZERO {
-            let level = &mut levels[i];
-
-            if level.quantity <= remaining_qty {
-                let trade_qty = level.quantity;
-                total_cost += level.price * trade_qty;
-                filled_qty += trade_qty;
-                remaining_qty -= trade_qty;
-                levels.remove(i);
-            } else {
-                let trade_qty = remaining_qty;
-                total_cost += level.price * trade_qty;
-                filled_qty += trade_qty;
-                level.quantity -= trade_qty;
-                remaining_qty = Decimal::ZERO;
-                i += 1;
-            }
+    /// Unrealized PnL in basis points of the entry notional.
+    ///
+    /// Computed as `pnl / (entry_price * quantity) * 10000`. Returns `None` if
+    /// `calculate_pnl` does or the entry notional is zero.
+    pub fn calculate_pnl_bps(&self, book: &OrderBook) -> Option<Decimal> {
+        let notional = self.entry_price.checked_mul(self.quantity)?;
+        if notional.is_zero() {
+            return None;
         }
+        self.calculate_pnl(book)?
+            .checked_div(notional)?
+            .checked_mul(Decimal::from(10_000))
+    }
 
-        if filled_qty == Decimal::ZERO {
-            return Err(OrderBookError::NotEnoughLiquidity(quantity, Decimal::ZERO));
+    /// Combines two fills on the same side into a single position.
+    ///
+    /// Quantities are summed and the entry price becomes the quantity-weighted
+    /// average of both entries.
+    ///
+    /// Returns `SideMismatch` if the positions are on different sides and
+    /// `Overflow` if the combined notional exceeds the range of `Decimal`.
+    pub fn merge(&self, other: &Position) -> Result<Position, OrderBookError> {
+        if self.side != other.side {
+            return Err(OrderBookError::SideMismatch(self.side, other.side));
         }
 
-        let avg_price = total_cost / filled_qty;
+        let quantity = self
+            .quantity
+            .checked_add(other.quantity)
+            .ok_or(OrderBookError::Overflow)?;
+        let entry_price = if quantity.is_zero() {
+            self.entry_price
+        } else {
+            self.entry_price
+                .checked_mul(self.quantity)
+                .zip(other.entry_price.checked_mul(other.quantity))
+                .and_then(|(a, b)| a.checked_add(b))
+                .and_then(|notional| notional.checked_div(quantity))
+                .ok_or(OrderBookError::Overflow)?
+        };
 
         Ok(Position {
-            side,
-            quantity: filled_qty,
-            entry_price: avg_price,
+            side: self.side,
+            quantity,
+            entry_price,
         })
     }
-}
 
-impl fmt::Display for OrderBook {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "Order Book:")?;
-        writeln!(
-            f,
-            "  ASKS (Top): {:?}",
-            self.asks.iter().take(3).collect::<Vec<_>>()
-        )?;
-        writeln!(
-            f,
-            "  BIDS (Top): {:?}",
-            self.bids.iter().take(3).collect::<Vec<_>>()
-        )
+    /// Closes `quantity` units of the position against the book.
+    ///
+    /// A long position sells into the Bids, a short position buys from the Asks,
+    /// consuming liquidity like `execute_market_order`. The open quantity is
+    /// reduced by the amount actually filled (which may be less than requested
+    /// if the book runs dry) and the realized PnL of the closed portion,
+    /// computed at the fill VWAP, is returned.
+    ///
+    /// # Errors
+    /// * `NonPositiveValue` - if `quantity` is not positive.
+    /// * `ReduceExceedsPosition` - if `quantity` is larger than the open size.
+    /// * `NotEnoughLiquidity` - if nothing could be filled.
+    /// * `Overflow` - if the realized PnL exceeds the range of `Decimal` (the
+    ///   book has already been consumed at that point).
+    pub fn reduce(
+        &mut self,
+        book: &mut OrderBook,
+        quantity: Decimal,
+    ) -> Result<Decimal, OrderBookError> {
+        if quantity <= Decimal::ZERO {
+            return Err(OrderBookError::NonPositiveValue(quantity));
+        }
+        if quantity > self.quantity {
+            return Err(OrderBookError::ReduceExceedsPosition(
+                quantity,
+                self.quantity,
+            ));
+        }
+
+        let exit = book.execute_market_order(self.side.opposite(), quantity)?;
+
+        let closed = Position {
+            quantity: exit.quantity,
+            ..*self
+        };
+        let realized = closed
+            .pnl_at(exit.entry_price)
+            .ok_or(OrderBookError::Overflow)?;
+        self.quantity -= exit.quantity;
+        Ok(realized)
+    }
+
+    /// Accumulates another same-side fill into this position (average down / up).
+    ///
+    /// In-place counterpart of `merge`: `self` ends up with the summed quantity
+    /// and the quantity-weighted entry price. On `SideMismatch`, `self` is left
+    /// unchanged.
+    pub fn merge_in_place(&mut self, other: &Position) -> Result<(), OrderBookError> {
+        *self = self.merge(other)?;
+        Ok(())
     }
 }
 
-/// Parses a raw string input into an `OrderBook` struct.
-///
-/// Validates the structure against the grammar and business rules (Instrument Config).
-///
-/// # Arguments
-/// * `input` - The string snapshot (e.g., "BIDS:100,1;ASKS:101,1").
-/// * `config` - Optional instrument configuration for strict validation.
-pub fn parse_order_book(
-    input: &str,
-    config: Option<&InstrumentConfig>,
-) -> Result<OrderBook, OrderBookError> {
-    let mut parsed = OrderBookParser::parse(Rule::order_book, input)?;
-    let root = parsed
-        .next()
-        .ok_or_else(|| OrderBookError::MissingSection("Empty input".into()))?;
+/// A collection of positions marked against the same order book.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Portfolio {
+    pub positions: Vec<Position>,
+}
 
-    let mut book = OrderBook::default();
+impl Portfolio {
+    /// Creates an empty portfolio.
+    pub fn new() -> Self {
+        Self::default()
+    }
 
-    for record in root.into_inner() {
-        match record.as_rule() {
-            Rule::bids_side => book.bids = parse_levels(record)?,
-            Rule::asks_side => book.asks = parse_levels(record)?,
-            _ => {}
-        }
+    /// Adds a position to the portfolio.
+    pub fn add(&mut self, position: Position) {
+        self.positions.push(position);
     }
 
-    validate_book_logic(&book)?;
-    if let Some(cfg) = config {
-        validate_instrument_rules(&book, cfg)?;
+    /// Sums the unrealized PnL of every position against `book`.
+    ///
+    /// Returns `UnmarkablePosition` with the index of the first position that
+    /// cannot be marked because its exit side of the book is empty, and
+    /// `Overflow` if a PnL or the total exceeds the range of `Decimal`.
+    pub fn total_pnl(&self, book: &OrderBook) -> Result<Decimal, OrderBookError> {
+        let mut total = Decimal::ZERO;
+        for (index, position) in self.positions.iter().enumerate() {
+            let exit = position
+                .touch_exit_price(book)
+                .ok_or(OrderBookError::UnmarkablePosition(index))?;
+            total = position
+                .pnl_at(exit)
+                .and_then(|pnl| total.checked_add(pnl))
+                .ok_or(OrderBookError::Overflow)?;
+        }
+        Ok(total)
     }
 
-    Ok(book)
+    /// Net quantity exposure: long (Buy) quantity minus short (Sell) quantity.
+    ///
+    /// The running total saturates at `Decimal::MAX` / `Decimal::MIN` instead
+    /// of overflowing.
+    pub fn net_exposure(&self) -> Decimal {
+        self.positions
+            .iter()
+            .fold(Decimal::ZERO, |total, position| match position.side {
+                Side::Buy => total.saturating_add(position.quantity),
+                Side::Sell => total.saturating_sub(position.quantity),
+            })
+    }
 }
 
-fn parse_levels(pair: pest::iterators::Pair<Rule>) -> Result<Vec<Level>, OrderBookError> {
-    let mut levels = Vec::new();
-    for inner in pair.into_inner() {
-        if inner.as_rule() == Rule::level_list {
-            for level_pair in inner.into_inner() {
-                if level_pair.as_rule() == Rule::level {
-                    let mut nums = level_pair.into_inner();
-                    let price_str = nums
-                        .next()
-                        .ok_or_else(|| OrderBookError::MissingSection("Missing price".into()))?
-                        .as_str();
-                    let qty_str = nums
-                        .next()
-                        .ok_or_else(|| OrderBookError::MissingSection("Missing quantity".into()))?
-                        .as_str();
-                    levels.push(Level {
-                        price: Decimal::from_str_exact(price_str)?,
-                        quantity: Decimal::from_str_exact(qty_str)?,
-                    });
-                }
-            }
+impl OrderBook {
+    /// Creates an empty book with room for `bids` and `asks` levels preallocated.
+    pub fn with_capacity(bids: usize, asks: usize) -> OrderBook {
+        OrderBook {
+            bids: Vec::with_capacity(bids),
+            asks: Vec::with_capacity(asks),
+            ..OrderBook::default()
         }
     }
-    Ok(levels)
-}
 
-fn validate_book_logic(book: &OrderBook) -> Result<(), OrderBookError> {
-    for window in book.bids.windows(2) {
-        if window[0].price == window[1].price {
-            return Err(OrderBookError::DuplicatePrice(window[0].price));
-        }
-        if window[0].price < window[1].price {
-            return Err(OrderBookError::BidsUnsorted(window[1].price));
-        }
+    /// Removes every level from both sides and resets `symbol` and
+    /// `currency`, keeping the allocated level capacity.
+    pub fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+        self.symbol = None;
+        self.currency = None;
     }
-    for window in book.asks.windows(2) {
-        if window[0].price == window[1].price {
-            return Err(OrderBookError::DuplicatePrice(window[0].price));
-        }
-        if window[0].price > window[1].price {
-            return Err(OrderBookError::AsksUnsorted(window[1].price));
-        }
+
+    /// Iterates over the bid levels, best (highest) price first.
+    pub fn iter_bids(&self) -> impl Iterator<Item = &Level> {
+        self.bids.iter()
     }
-    if let (Some(bid), Some(ask)) = (book.bids.first(), book.asks.first())
-        && bid.price >= ask.price
-    {
-        return Err(OrderBookError::CrossedBook(bid.price, ask.price));
+
+    /// Iterates over the ask levels, best (lowest) price first.
+    pub fn iter_asks(&self) -> impl Iterator<Item = &Level> {
+        self.asks.iter()
     }
-    Ok(())
-}
 
-fn validate_instrument_rules(
-    book: &OrderBook,
-    config: &InstrumentConfig,
-) -> Result<(), OrderBookError> {
-    let all_levels = book.bids.iter().chain(book.asks.iter());
-    for level in all_levels {
+    /// Iterates over every level in the book, tagged with its side.
+    ///
+    /// The order is deterministic: all bids (best first), then all asks (best first).
+    pub fn iter_levels(&self) -> impl Iterator<Item = (Side, &Level)> {
+        self.iter_bids()
+            .map(|level| (Side::Buy, level))
+            .chain(self.iter_asks().map(|level| (Side::Sell, level)))
+    }
+
+    /// Number of bid levels in the book.
+    pub fn bid_depth(&self) -> usize {
+        self.bids.len()
+    }
+
+    /// Number of ask levels in the book.
+    pub fn ask_depth(&self) -> usize {
+        self.asks.len()
+    }
+
+    /// Returns `true` if both sides are empty (no market at all).
+    pub fn is_empty(&self) -> bool {
+        self.bids.is_empty() && self.asks.is_empty()
+    }
+
+    /// Returns the best (highest) tradeable bid level.
+    ///
+    /// When `skip_zero` is set, zero-quantity levels are skipped and the first
+    /// level with a positive quantity is returned instead.
+    pub fn best_bid(&self, skip_zero: bool) -> Option<&Level> {
+        self.bids
+            .iter()
+            .find(|level| level.tradeable && (!skip_zero || !level.quantity.is_zero()))
+    }
+
+    /// Returns the best (lowest) tradeable ask level.
+    ///
+    /// When `skip_zero` is set, zero-quantity levels are skipped and the first
+    /// level with a positive quantity is returned instead.
+    pub fn best_ask(&self, skip_zero: bool) -> Option<&Level> {
+        self.asks
+            .iter()
+            .find(|level| level.tradeable && (!skip_zero || !level.quantity.is_zero()))
+    }
+
+    /// Returns the `n`th-best tradeable level on a side, counting from the
+    /// touch (`0`).
+    ///
+    /// `Side::Buy` indexes the bids, `Side::Sell` the asks. As with `best_bid`
+    /// and `best_ask`, non-tradeable levels are skipped. Returns `None` if the
+    /// side has `n` or fewer tradeable levels.
+    pub fn level_at(&self, side: Side, n: usize) -> Option<&Level> {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        tradeable_levels(levels).nth(n)
+    }
+
+    /// Returns the best bid and best ask prices and quantities in one struct.
+    ///
+    /// Uses the same tradeable touch levels as `best_bid` and `best_ask`.
+    /// Returns `None` if either side has no tradeable level.
+    pub fn top_of_book(&self) -> Option<TopOfBook> {
+        let bid = self.best_bid(false)?;
+        let ask = self.best_ask(false)?;
+        Some(TopOfBook {
+            bid_price: bid.price,
+            bid_qty: bid.quantity,
+            ask_price: ask.price,
+            ask_qty: ask.quantity,
+        })
+    }
+
+    /// Returns the mid price `(best_bid + best_ask) / 2`.
+    ///
+    /// Returns `None` if either side of the book is empty or the sum of the
+    /// two prices overflows `Decimal`.
+    pub fn mid_price(&self) -> Option<Decimal> {
+        let bid = self.best_bid(false)?.price;
+        let ask = self.best_ask(false)?.price;
+        Some(bid.checked_add(ask)? / Decimal::TWO)
+    }
+
+    /// Returns the bid-ask spread in basis points of the mid price:
+    /// `(best_ask - best_bid) / mid_price * 10000`.
+    ///
+    /// Returns `None` if either side is empty, the mid price is zero or the
+    /// computation overflows `Decimal`.
+    pub fn spread_bps(&self) -> Option<Decimal> {
+        let bid = self.best_bid(false)?.price;
+        let ask = self.best_ask(false)?.price;
+        let mid = bid.checked_add(ask)? / Decimal::TWO;
+        if mid.is_zero() {
+            return None;
+        }
+        ask.checked_sub(bid)?
+            .checked_div(mid)?
+            .checked_mul(Decimal::from(10_000))
+    }
+
+    /// Returns a deterministic reference price for reporting.
+    ///
+    /// * Both sides present: the mid price.
+    /// * One side present: the best price of that side.
+    /// * Empty book, or a mid price that overflows `Decimal`: `fallback`.
+    pub fn reference_price(&self, fallback: Decimal) -> Decimal {
+        match (self.best_bid(false), self.best_ask(false)) {
+            (Some(bid), Some(ask)) => bid
+                .price
+                .checked_add(ask.price)
+                .map_or(fallback, |sum| sum / Decimal::TWO),
+            (Some(bid), None) => bid.price,
+            (None, Some(ask)) => ask.price,
+            (None, None) => fallback,
+        }
+    }
+
+    /// Runs the logical checks applied by `parse_order_book` on this book.
+    ///
+    /// Useful for books built programmatically rather than parsed. Checks that
+    /// bids are sorted descending, asks ascending, no side repeats a price and
+    /// the tradeable touch is neither locked nor crossed. Errors carry no input
+    /// spans.
+    pub fn validate(&self) -> Result<(), OrderBookError> {
+        validate_book_logic(
+            self,
+            &LevelSpans::default(),
+            &ParseOptions::default(),
+            &mut Vec::new(),
+        )
+    }
+
+    /// Checks every level against an instrument's price bounds, tick size,
+    /// minimum lot and lot step.
+    ///
+    /// These are the same rules `parse_order_book` enforces when given a config.
+    pub fn validate_instrument(&self, config: &InstrumentConfig) -> Result<(), OrderBookError> {
+        validate_instrument_rules(self, config)
+    }
+
+    /// Volume-weighted average price of the top `n` tradeable levels of a side.
+    ///
+    /// `Side::Buy` averages the bids, `Side::Sell` the asks. Unlike a fill
+    /// simulation this only describes the standing book. `n` is clamped to the
+    /// number of available levels.
+    ///
+    /// Returns `None` if the side is empty, its total quantity is zero or its
+    /// notional overflows `Decimal`.
+    pub fn vwap_top(&self, side: Side, n: usize) -> Option<Decimal> {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        let top = tradeable_levels(levels).take(n);
+
+        let total_qty = checked_quantity(top.clone())?;
+        if total_qty.is_zero() {
+            return None;
+        }
+        checked_notional(top)?.checked_div(total_qty)
+    }
+
+    /// Herfindahl concentration index of the tradeable quantity on a side.
+    ///
+    /// `Side::Buy` measures the bids, `Side::Sell` the asks. The index is the
+    /// sum of each level's squared share of the side's total quantity: `1`
+    /// when all liquidity sits on one level, approaching `1 / n` when it is
+    /// spread evenly over `n` levels.
+    ///
+    /// Returns `None` if the side is empty, its total quantity is zero or
+    /// overflows `Decimal`.
+    pub fn concentration(&self, side: Side) -> Option<Decimal> {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        let total = checked_quantity(tradeable_levels(levels))?;
+        if total.is_zero() {
+            return None;
+        }
+        Some(
+            tradeable_levels(levels)
+                .map(|level| {
+                    let share = level.quantity / total;
+                    share * share
+                })
+                .sum(),
+        )
+    }
+
+    /// Quantity-weighted average age of the resting liquidity at time `now`.
+    ///
+    /// Only tradeable levels with a timestamp take part; each contributes
+    /// `now - timestamp` weighted by its quantity, on both sides. The result is
+    /// in the same units as the timestamps. A large value flags a stale book.
+    ///
+    /// Returns `None` if no level carries a timestamp, their total quantity
+    /// is zero or the weighted sum overflows `Decimal`.
+    pub fn weighted_age(&self, now: i64) -> Option<Decimal> {
+        let mut weighted = Decimal::ZERO;
+        let mut total = Decimal::ZERO;
+        for (_, level) in self.iter_levels().filter(|(_, level)| level.tradeable) {
+            if let Some(timestamp) = level.timestamp {
+                let age = Decimal::from(i128::from(now) - i128::from(timestamp));
+                weighted = weighted.checked_add(age.checked_mul(level.quantity)?)?;
+                total = total.checked_add(level.quantity)?;
+            }
+        }
+        if total.is_zero() {
+            return None;
+        }
+        weighted.checked_div(total)
+    }
+
+    /// Depth-weighted fair value over the top `levels` tradeable levels of
+    /// each side.
+    ///
+    /// Let `P_b`, `Q_b` be the VWAP and total quantity of the top `levels`
+    /// bids, and `P_a`, `Q_a` the same for the asks. The fair value is
+    ///
+    /// `(P_b * Q_a + P_a * Q_b) / (Q_a + Q_b)`
+    ///
+    /// so each side's price is weighted by the depth resting on the *other*
+    /// side: a heavy bid stack pulls the value towards the ask. With
+    /// `levels = 1` this is the classic top-of-book microprice.
+    ///
+    /// Returns `None` if `levels` is zero, either side is empty or has zero
+    /// total quantity within the window, or a side's notional overflows `Decimal`.
+    pub fn fair_value(&self, levels: usize) -> Option<Decimal> {
+        let side = |side_levels: &[Level]| -> Option<(Decimal, Decimal)> {
+            let top = tradeable_levels(side_levels).take(levels);
+            let quantity = checked_quantity(top.clone())?;
+            if quantity.is_zero() {
+                return None;
+            }
+            Some((checked_notional(top)?.checked_div(quantity)?, quantity))
+        };
+
+        let (bid_price, bid_qty) = side(&self.bids)?;
+        let (ask_price, ask_qty) = side(&self.asks)?;
+        let numerator = bid_price
+            .checked_mul(ask_qty)?
+            .checked_add(ask_price.checked_mul(bid_qty)?)?;
+        numerator.checked_div(bid_qty.checked_add(ask_qty)?)
+    }
+
+    /// Infers the effective tick size of the bids and of the asks separately.
+    ///
+    /// Each side's tick is the greatest common divisor of the price differences
+    /// between its adjacent levels. Comparing the two values exposes malformed
+    /// data where each side uses a different price grid. A side yields `None`
+    /// if it has fewer than two distinct prices.
+    pub fn inferred_tick_per_side(&self) -> (Option<Decimal>, Option<Decimal>) {
+        (
+            infer_tick(self.bids.iter().map(|level| level.price)),
+            infer_tick(self.asks.iter().map(|level| level.price)),
+        )
+    }
+
+    /// Infers the instrument's tick size from the prices in the book.
+    ///
+    /// Walks every price from the deepest bid up to the deepest ask and takes
+    /// the greatest common divisor of the gaps between adjacent prices, so the
+    /// spread counts as well. This is a heuristic for bootstrapping an
+    /// `InstrumentConfig`; sparse books may yield a multiple of the real tick.
+    ///
+    /// Returns `None` if the book has fewer than two distinct prices.
+    pub fn infer_tick_size(&self) -> Option<Decimal> {
+        infer_tick(
+            self.bids
+                .iter()
+                .rev()
+                .chain(&self.asks)
+                .map(|level| level.price),
+        )
+    }
+
+    /// Reference price using every tradeable level, with exponentially
+    /// decaying weights.
+    ///
+    /// On each side the level at rank `r` (0 = touch) gets weight `decay^r`, and
+    /// the side price is the weighted average of its level prices. The result
+    /// is the average of the bid and ask side prices. With `decay = 0` only the
+    /// touch counts and the result equals the mid price; with `decay = 1` every
+    /// level counts equally.
+    ///
+    /// Weights depend only on rank, not on quantity, so unlike `fair_value`
+    /// (the microprice for one level) this does not lean towards the heavier
+    /// side of the touch.
+    ///
+    /// Returns `None` if either side has no tradeable level, `decay` is outside
+    /// `[0, 1]`, or the weighted sums overflow `Decimal`.
+    pub fn decay_weighted_mid(&self, decay: Decimal) -> Option<Decimal> {
+        if decay < Decimal::ZERO || decay > Decimal::ONE {
+            return None;
+        }
+
+        let side_price = |levels: &[Level]| -> Option<Decimal> {
+            let mut weight = Decimal::ONE;
+            let mut weighted_sum = Decimal::ZERO;
+            let mut weight_sum = Decimal::ZERO;
+            for level in tradeable_levels(levels) {
+                weighted_sum = weighted_sum.checked_add(level.price.checked_mul(weight)?)?;
+                weight_sum = weight_sum.checked_add(weight)?;
+                weight *= decay;
+            }
+            if weight_sum.is_zero() {
+                return None;
+            }
+            weighted_sum.checked_div(weight_sum)
+        };
+
+        let bid = side_price(&self.bids)?;
+        let ask = side_price(&self.asks)?;
+        Some(bid.checked_add(ask)? / Decimal::TWO)
+    }
+
+    /// Repairs a crossed or locked book using `UncrossStrategy::DropSmaller`.
+    ///
+    /// See `uncross_with` for details. Returns the number of levels removed.
+    pub fn uncross(&mut self) -> usize {
+        self.uncross_with(UncrossStrategy::default())
+    }
+
+    /// Removes touch levels until the best bid is strictly below the best ask.
+    ///
+    /// While the best bid is at or above the best ask, one of the two levels
+    /// is dropped according to `strategy` and the check repeats, so several
+    /// levels may go. As in parsing validation, the touch is the first
+    /// tradeable level of each side; non-tradeable levels are never removed.
+    /// Books that are already uncrossed or one-sided are left untouched.
+    ///
+    /// Returns the number of levels removed.
+    pub fn uncross_with(&mut self, strategy: UncrossStrategy) -> usize {
+        let mut removed = 0;
+        let touch = |levels: &[Level]| levels.iter().position(|level| level.tradeable);
+        while let (Some(bid_index), Some(ask_index)) = (touch(&self.bids), touch(&self.asks)) {
+            let (bid, ask) = (&self.bids[bid_index], &self.asks[ask_index]);
+            if bid.price < ask.price {
+                break;
+            }
+            let drop_bid = match strategy {
+                UncrossStrategy::DropSmaller => bid.quantity <= ask.quantity,
+                UncrossStrategy::DropBids => true,
+                UncrossStrategy::DropAsks => false,
+            };
+            if drop_bid {
+                self.bids.remove(bid_index);
+            } else {
+                self.asks.remove(ask_index);
+            }
+            removed += 1;
+        }
+        removed
+    }
+
+    /// Keeps only the `n` levels nearest the touch on each side.
+    ///
+    /// Since bids are sorted descending and asks ascending, these are the
+    /// first `n` entries of each side; deeper levels are dropped.
+    pub fn truncate_depth(&mut self, n: usize) {
+        self.bids.truncate(n);
+        self.asks.truncate(n);
+    }
+
+    /// Returns a copy of the book limited to the top `n` levels per side.
+    pub fn top_n(&self, n: usize) -> OrderBook {
+        let mut book = self.clone();
+        book.truncate_depth(n);
+        book
+    }
+
+    /// Compares two books level by level within the given tolerances.
+    ///
+    /// Both sides must have the same number of levels, and each pair of
+    /// corresponding levels must differ by at most `price_tol` in price and
+    /// `qty_tol` in quantity. Other level attributes are ignored.
+    pub fn approx_eq(&self, other: &OrderBook, price_tol: Decimal, qty_tol: Decimal) -> bool {
+        let side_eq = |a: &[Level], b: &[Level]| {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(x, y)| {
+                    (x.price - y.price).abs() <= price_tol
+                        && (x.quantity - y.quantity).abs() <= qty_tol
+                })
+        };
+        side_eq(&self.bids, &other.bids) && side_eq(&self.asks, &other.asks)
+    }
+
+    /// Turns fractional quantities parsed with `QuantityFormat::Percent` into
+    /// absolute quantities by multiplying every level by `total`.
+    ///
+    /// A level parsed from `25%` holds `0.25`, so with a `total` of `400` it
+    /// ends up with `100`.
+    pub fn resolve_percent_quantities(&mut self, total: Decimal) {
+        for level in self.bids.iter_mut().chain(self.asks.iter_mut()) {
+            level.quantity *= total;
+        }
+    }
+
+    /// Builds the synthetic inverse book, quoting the pair the other way round.
+    ///
+    /// Each bid at price `p` becomes an ask at `1/p` and each ask becomes a bid
+    /// at `1/p`; quantities, tradeability and order ids are carried over
+    /// unchanged. Both resulting sides are re-sorted. Levels with a price of
+    /// zero or below have no inverse and are dropped. The symbol and currency
+    /// describe the original quote and are not carried over.
+    pub fn invert(&self) -> OrderBook {
+        let inverse = |levels: &[Level]| -> Vec<Level> {
+            levels
+                .iter()
+                .filter(|level| level.price > Decimal::ZERO)
+                .map(|level| Level {
+                    price: Decimal::ONE / level.price,
+                    ..level.clone()
+                })
+                .collect()
+        };
+
+        let mut bids = inverse(&self.asks);
+        let mut asks = inverse(&self.bids);
+        bids.sort_by_key(|level| core::cmp::Reverse(level.price));
+        asks.sort_by_key(|level| level.price);
+        OrderBook {
+            bids,
+            asks,
+            ..OrderBook::default()
+        }
+    }
+
+    /// Estimates Kyle's lambda (linear price impact per unit of volume).
+    ///
+    /// A market order of the given `side` walks the opposite side of the book
+    /// (Buy consumes Asks, Sell consumes Bids). For each of the top `depth`
+    /// tradeable levels on that side a point is formed:
+    ///
+    /// * **x** - cumulative quantity from the touch up to and including the level.
+    /// * **y** - absolute price distance of the level from the touch.
+    ///
+    /// Lambda is the ordinary least squares slope of `y` on `x`, i.e.
+    /// `Σ(x - x̄)(y - ȳ) / Σ(x - x̄)²`, so it is always expressed as a positive
+    /// price move per unit of quantity regardless of side.
+    ///
+    /// Returns `None` if fewer than two levels are available or the fit is
+    /// degenerate, including when the sums overflow `Decimal`.
+    pub fn kyle_lambda(&self, side: Side, depth: usize) -> Option<Decimal> {
+        let levels = match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        };
+        let levels: Vec<&Level> = tradeable_levels(levels).take(depth).collect();
+        if levels.len() < 2 {
+            return None;
+        }
+
+        let touch = levels[0].price;
+        let mut cumulative = Decimal::ZERO;
+        let mut points: Vec<(Decimal, Decimal)> = Vec::with_capacity(levels.len());
+        for level in &levels {
+            cumulative = cumulative.checked_add(level.quantity)?;
+            points.push((cumulative, level.price.checked_sub(touch)?.abs()));
+        }
+
+        let count = Decimal::from(points.len());
+        let mut sum_x = Decimal::ZERO;
+        let mut sum_y = Decimal::ZERO;
+        for (x, y) in &points {
+            sum_x = sum_x.checked_add(*x)?;
+            sum_y = sum_y.checked_add(*y)?;
+        }
+        let mean_x = sum_x / count;
+        let mean_y = sum_y / count;
+
+        let mut covariance = Decimal::ZERO;
+        let mut variance = Decimal::ZERO;
+        for (x, y) in &points {
+            let dx = x.checked_sub(mean_x)?;
+            let dy = y.checked_sub(mean_y)?;
+            covariance = covariance.checked_add(dx.checked_mul(dy)?)?;
+            variance = variance.checked_add(dx.checked_mul(dx)?)?;
+        }
+
+        if variance.is_zero() {
+            return None;
+        }
+        covariance.checked_div(variance)
+    }
+
+    /// Returns the quantity resting at exactly `price` on the given side.
+    ///
+    /// `Side::Buy` looks up the bids, `Side::Sell` the asks. Relies on the
+    /// validated sort order (bids descending, asks ascending) to binary-search
+    /// the side instead of scanning it.
+    pub fn quantity_at(&self, side: Side, price: Decimal) -> Option<Decimal> {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        search_level(levels, side, price)
+            .ok()
+            .map(|index| levels[index].quantity)
+    }
+
+    /// Total tradeable quantity a market order on `side` could consume.
+    ///
+    /// `Side::Buy` sums the asks, `Side::Sell` the bids; non-tradeable levels
+    /// are excluded. An order of this size empties the side. The sum saturates
+    /// at `Decimal::MAX` instead of overflowing.
+    pub fn liquidity(&self, side: Side) -> Decimal {
+        let levels = match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        };
+        levels
+            .iter()
+            .filter(|level| level.tradeable)
+            .fold(Decimal::ZERO, |total, level| {
+                total.saturating_add(level.quantity)
+            })
+    }
+
+    /// Total tradeable quantity at prices within `[low, high]` (inclusive) on a
+    /// side.
+    ///
+    /// `Side::Buy` sums the bids, `Side::Sell` the asks. The bounds are located
+    /// by binary search over the sorted side. Returns `Decimal::ZERO` if no
+    /// tradeable level falls in the range. The sum saturates at `Decimal::MAX`.
+    pub fn liquidity_between(&self, side: Side, low: Decimal, high: Decimal) -> Decimal {
+        let (levels, start, end) = match side {
+            Side::Buy => (
+                &self.bids,
+                self.bids.partition_point(|level| level.price > high),
+                self.bids.partition_point(|level| level.price >= low),
+            ),
+            Side::Sell => (
+                &self.asks,
+                self.asks.partition_point(|level| level.price < low),
+                self.asks.partition_point(|level| level.price <= high),
+            ),
+        };
+        if start >= end {
+            return Decimal::ZERO;
+        }
+        tradeable_levels(&levels[start..end]).fold(Decimal::ZERO, |total, level| {
+            total.saturating_add(level.quantity)
+        })
+    }
+
+    /// Volume resting within `pct` percent of the mid price on each side.
+    ///
+    /// Sums tradeable bids priced at or above `mid * (1 - pct/100)` and asks
+    /// priced at or below `mid * (1 + pct/100)`, so `pct = 1` covers a 1% band.
+    ///
+    /// Returns `(bid_volume, ask_volume)`, or `None` if the mid price is
+    /// unavailable, `pct` is negative or the band overflows `Decimal`.
+    pub fn depth_within_pct(&self, pct: Decimal) -> Option<(Decimal, Decimal)> {
+        if pct < Decimal::ZERO {
+            return None;
+        }
+        let mid = self.mid_price()?;
+        let band = mid.checked_mul(pct)? / Decimal::ONE_HUNDRED;
+        Some((
+            self.liquidity_between(Side::Buy, mid.saturating_sub(band), Decimal::MAX),
+            self.liquidity_between(Side::Sell, Decimal::MIN, mid.saturating_add(band)),
+        ))
+    }
+
+    /// Running depth of a side for staircase depth charts.
+    ///
+    /// Yields `(price, quantity, cumulative_quantity)` for every tradeable level
+    /// of the side in book order (`Side::Buy` = bids, `Side::Sell` = asks), where the
+    /// cumulative quantity sums from the touch outward up to and including
+    /// that level, saturating at `Decimal::MAX`.
+    pub fn cumulative(&self, side: Side) -> Vec<(Decimal, Decimal, Decimal)> {
+        let levels = match side {
+            Side::Buy => &self.bids,
+            Side::Sell => &self.asks,
+        };
+        let mut running = Decimal::ZERO;
+        tradeable_levels(levels)
+            .map(|level| {
+                running = running.saturating_add(level.quantity);
+                (level.price, level.quantity, running)
+            })
+            .collect()
+    }
+
+    /// Simulates spending up to `notional` with a market order, without mutating the book.
+    ///
+    /// A `Side::Buy` order walks the asks, a `Side::Sell` order the bids,
+    /// skipping non-tradeable levels. Whole levels are taken while their
+    /// `price * quantity` fits in the remaining notional; the last level is
+    /// filled proportionally.
+    ///
+    /// Returns `(average_price, total_quantity)`, or `None` if the opposite side
+    /// has no tradeable liquidity, `notional` is not positive or the filled
+    /// quantity overflows `Decimal`.
+    pub fn fill_for_notional(&self, side: Side, notional: Decimal) -> Option<(Decimal, Decimal)> {
+        if notional <= Decimal::ZERO {
+            return None;
+        }
+        let levels = match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        };
+
+        let mut remaining = notional;
+        let mut total_cost = Decimal::ZERO;
+        let mut filled_qty = Decimal::ZERO;
+        for level in levels.iter().filter(|level| level.tradeable) {
+            if remaining <= Decimal::ZERO {
+                break;
+            }
+            // A notional too large for `Decimal` certainly exceeds `remaining`.
+            let whole_level = level
+                .price
+                .checked_mul(level.quantity)
+                .filter(|level_notional| *level_notional <= remaining);
+            if let Some(level_notional) = whole_level {
+                total_cost += level_notional;
+                filled_qty = filled_qty.checked_add(level.quantity)?;
+                remaining -= level_notional;
+            } else {
+                total_cost += remaining;
+                filled_qty = filled_qty.checked_add(remaining.checked_div(level.price)?)?;
+                remaining = Decimal::ZERO;
+            }
+        }
+
+        if filled_qty.is_zero() {
+            return None;
+        }
+        Some((total_cost.checked_div(filled_qty)?, filled_qty))
+    }
+
+    /// Relative price impact of filling `quantity` with a market order.
+    ///
+    /// A `Side::Buy` order walks the asks, a `Side::Sell` order the bids,
+    /// skipping non-tradeable levels. The impact is the distance from the touch
+    /// price to the fill VWAP, relative to the touch: `(vwap - touch) / touch`
+    /// for a buy and `(touch - vwap) / touch` for a sell, so it is never
+    /// negative. The result is a fraction (`0.01` = 1%).
+    ///
+    /// Returns `None` if the side cannot fill the whole `quantity`, if
+    /// `quantity` is not positive, or if the touch price is zero.
+    pub fn price_impact(&self, side: Side, quantity: Decimal) -> Option<Decimal> {
+        if quantity <= Decimal::ZERO {
+            return None;
+        }
+        let levels = match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        };
+
+        let touch = levels.iter().find(|level| level.tradeable)?.price;
+        if touch.is_zero() {
+            return None;
+        }
+
+        let (vwap, filled) = simulate_fill(levels, quantity)?;
+        if filled < quantity {
+            return None;
+        }
+        Some(match side {
+            Side::Buy => (vwap - touch) / touch,
+            Side::Sell => (touch - vwap) / touch,
+        })
+    }
+
+    /// Quantity a market order must take to clear the book up to `target_price`.
+    ///
+    /// A `Side::Buy` order sums the asks priced at or below `target_price`; a
+    /// `Side::Sell` order sums the bids priced at or above it. Non-tradeable
+    /// levels are skipped, as in execution. Returns `Decimal::ZERO` if the
+    /// target does not reach the touch. The sum saturates at `Decimal::MAX`.
+    pub fn quantity_to_price(&self, side: Side, target_price: Decimal) -> Decimal {
+        let levels = match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        };
+        levels
+            .iter()
+            .take_while(|level| match side {
+                Side::Buy => level.price <= target_price,
+                Side::Sell => level.price >= target_price,
+            })
+            .filter(|level| level.tradeable)
+            .fold(Decimal::ZERO, |total, level| {
+                total.saturating_add(level.quantity)
+            })
+    }
+
+    /// Worst price a market order of `target_qty` would touch.
+    ///
+    /// The inverse of `quantity_to_price`: a `Side::Buy` order walks the asks,
+    /// a `Side::Sell` order the bids, skipping non-tradeable levels, and the
+    /// price of the level where the cumulative quantity first reaches
+    /// `target_qty` is returned. Unlike a VWAP, this is the marginal price.
+    ///
+    /// Returns `None` if `target_qty` is not positive or the side does not hold
+    /// enough tradeable quantity.
+    pub fn price_at_cumulative_qty(&self, side: Side, target_qty: Decimal) -> Option<Decimal> {
+        if target_qty <= Decimal::ZERO {
+            return None;
+        }
+        let levels = match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        };
+        let mut running = Decimal::ZERO;
+        levels
+            .iter()
+            .filter(|level| level.tradeable)
+            .find(|level| {
+                running = running.saturating_add(level.quantity);
+                running >= target_qty
+            })
+            .map(|level| level.price)
+    }
+
+    /// Effective spread of filling `quantity` with a market order: `2 * |vwap - mid|`.
+    ///
+    /// A `Side::Buy` order walks the asks, a `Side::Sell` order the bids,
+    /// skipping non-tradeable levels. If the side holds less than `quantity`,
+    /// the VWAP of the partial fill is used.
+    ///
+    /// Returns `None` if the mid price is unavailable or nothing can be filled.
+    pub fn effective_spread(&self, side: Side, quantity: Decimal) -> Option<Decimal> {
+        let mid = self.mid_price()?;
+        let levels = match side {
+            Side::Buy => &self.asks,
+            Side::Sell => &self.bids,
+        };
+        let (vwap, _) = simulate_fill(levels, quantity)?;
+        Some(Decimal::TWO * (vwap - mid).abs())
+    }
+
+    /// Places a passive (Good-Till-Cancel) order on its own side of the book.
+    ///
+    /// `Side::Buy` rests on the bids, `Side::Sell` on the asks. The order is
+    /// inserted at its sorted position; if a level already exists at `price`
+    /// the quantity is added to it instead of producing a duplicate.
+    ///
+    /// # Errors
+    /// * `NonPositiveValue` - if `price` or `quantity` is not positive.
+    /// * `LockedBook` - if the order would sit exactly at the opposite best price.
+    /// * `CrossedBook` - if the order would go through the opposite best price.
+    /// * `Overflow` - if merging into an existing level exceeds the range of
+    ///   `Decimal`. The book is left unchanged.
+    pub fn place_resting(
+        &mut self,
+        side: Side,
+        price: Decimal,
+        quantity: Decimal,
+    ) -> Result<(), OrderBookError> {
+        for value in [price, quantity] {
+            if value <= Decimal::ZERO {
+                return Err(OrderBookError::NonPositiveValue(value));
+            }
+        }
+
+        match side {
+            Side::Buy => {
+                if let Some(ask) = self.best_ask(false) {
+                    check_touch(price, ask.price)?;
+                }
+            }
+            Side::Sell => {
+                if let Some(bid) = self.best_bid(false) {
+                    check_touch(bid.price, price)?;
+                }
+            }
+        }
+
+        let levels = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        match search_level(levels, side, price) {
+            Ok(index) => {
+                let merged = levels[index].quantity.checked_add(quantity);
+                levels[index].quantity = merged.ok_or(OrderBookError::Overflow)?;
+            }
+            Err(index) => levels.insert(index, Level::new_unchecked(price, quantity)),
+        }
+        Ok(())
+    }
+
+    /// Cancels the resting order with order ID `id`, searching both sides.
+    ///
+    /// The level carrying the ID is removed and returned; since a level with
+    /// an ID represents a single order, its whole quantity goes with it.
+    /// Returns `None` if no level has that ID.
+    pub fn cancel(&mut self, id: u64) -> Option<Level> {
+        for levels in [&mut self.bids, &mut self.asks] {
+            if let Some(index) = levels.iter().position(|level| level.id == Some(id)) {
+                return Some(levels.remove(index));
+            }
+        }
+        None
+    }
+
+    /// Sets the quantity resting at `price` on a side, as an incremental feed update.
+    ///
+    /// `Side::Buy` updates the bids, `Side::Sell` the asks. An existing level
+    /// keeps its other attributes and takes the new quantity; otherwise a new
+    /// level is inserted at its sorted position. A quantity of zero (or below)
+    /// removes the level if present.
+    pub fn apply_update(&mut self, side: Side, price: Decimal, quantity: Decimal) {
+        let levels = match side {
+            Side::Buy => &mut self.bids,
+            Side::Sell => &mut self.asks,
+        };
+        match (search_level(levels, side, price), quantity > Decimal::ZERO) {
+            (Ok(index), true) => levels[index].quantity = quantity,
+            (Ok(index), false) => {
+                levels.remove(index);
+            }
+            (Err(index), true) => levels.insert(index, Level::new_unchecked(price, quantity)),
+            (Err(_), false) => {}
+        }
+    }
+
+    /// Computes the updates that turn this book into `newer`.
+    ///
+    /// Each entry is `(side, price, new_quantity)` in the form accepted by
+    /// `apply_update`, with a quantity of zero marking a removed level. Only
+    /// prices whose quantity changed are listed; bids come first, then asks,
+    /// each in book order. Both sides are walked once in parallel, relying on
+    /// their sorted order.
+    ///
+    /// Only prices and quantities are compared, so applying the diff rebuilds
+    /// `newer` exactly as long as its levels have positive quantities and
+    /// default attributes (tradeable, no order ID).
+    pub fn diff(&self, newer: &OrderBook) -> Vec<(Side, Decimal, Decimal)> {
+        let mut changes = Vec::new();
+        diff_levels(Side::Buy, &self.bids, &newer.bids, &mut changes);
+        diff_levels(Side::Sell, &self.asks, &newer.asks, &mut changes);
+        changes
+    }
+
+    /// Matches an incoming limit order against the book and rests any remainder.
+    ///
+    /// A `Side::Buy` order walks the asks while their price is at or below
+    /// `price`; a `Side::Sell` order walks the bids while their price is at or
+    /// above `price`. Every fill executes at the resting level's price and is
+    /// recorded as a `Trade`, in execution order. Non-tradeable levels are
+    /// skipped. Whatever quantity is left rests on the order's own side at
+    /// `price`, merging into an existing level if there is one (the merged
+    /// quantity saturates at `Decimal::MAX`).
+    ///
+    /// Orders with a non-positive `price` or `quantity` are ignored and produce
+    /// no trades.
+    pub fn match_incoming(&mut self, side: Side, price: Decimal, quantity: Decimal) -> Vec<Trade> {
+        let mut trades = Vec::new();
+        if price <= Decimal::ZERO || quantity <= Decimal::ZERO {
+            return trades;
+        }
+
+        let maker_side = side.opposite();
+        let (own, opposite) = match side {
+            Side::Buy => (&mut self.bids, &mut self.asks),
+            Side::Sell => (&mut self.asks, &mut self.bids),
+        };
+
+        let mut remaining = quantity;
+        let mut i = 0;
+        while i < opposite.len() && remaining > Decimal::ZERO {
+            let level = &mut opposite[i];
+            if !level.tradeable {
+                i += 1;
+                continue;
+            }
+            let crosses = match side {
+                Side::Buy => level.price <= price,
+                Side::Sell => level.price >= price,
+            };
+            if !crosses {
+                break;
+            }
+
+            let fill = level.quantity.min(remaining);
+            trades.push(Trade {
+                price: level.price,
+                quantity: fill,
+                maker_side,
+            });
+            level.quantity -= fill;
+            remaining -= fill;
+            if level.quantity.is_zero() {
+                opposite.remove(i);
+            } else {
+                i += 1;
+            }
+        }
+
+        if remaining > Decimal::ZERO {
+            match search_level(own, side, price) {
+                Ok(index) => own[index].quantity = own[index].quantity.saturating_add(remaining),
+                Err(index) => own.insert(index, Level::new_unchecked(price, remaining)),
+            }
+        }
+        trades
+    }
+
+    /// Computes a Kraken-style CRC32 checksum over the top `depth` levels.
+    ///
+    /// The canonical form hashed is built by concatenating, for the top `depth`
+    /// asks (best first) followed by the top `depth` bids (best first), each
+    /// level's price and then quantity as written in the input, with the
+    /// decimal point removed and leading zeros stripped (`"0.0500"` -> `"500"`).
+    /// The checksum is the standard CRC-32 (IEEE) of that ASCII string.
+    pub fn crc32_top(&self, depth: usize) -> u32 {
+        let mut canonical = String::new();
+        for level in self
+            .asks
+            .iter()
+            .take(depth)
+            .chain(self.bids.iter().take(depth))
+        {
+            for value in [level.price, level.quantity] {
+                let digits = value.to_string().replace('.', "");
+                canonical.push_str(digits.trim_start_matches('0'));
+            }
+        }
+        crc32(canonical.as_bytes())
+    }
+
+    /// Computes a stable 64-bit fingerprint of every level's price and quantity.
+    ///
+    /// The hash is FNV-1a over the bids (best first), a side separator, then
+    /// the asks (best first), with each value written in its normalized decimal
+    /// form. Normalizing means `1.0` and `1.00` hash identically, so books that
+    /// compare equal always share a fingerprint. The result does not depend on
+    /// process state and is safe to persist for deduplication.
+    pub fn fingerprint(&self) -> u64 {
+        let mut hash = FNV_OFFSET_BASIS;
+        for (separator, levels) in [(b'B', &self.bids), (b'A', &self.asks)] {
+            hash = fnv1a(hash, &[separator]);
+            for level in levels {
+                for value in [level.price, level.quantity] {
+                    hash = fnv1a(hash, value.normalize().to_string().as_bytes());
+                    hash = fnv1a(hash, b";");
+                }
+            }
+        }
+        hash
+    }
+
+    /// Dumps the book into a canonical, fully precise multi-line fixture.
+    ///
+    /// Each level becomes one line: `<side> <price> <quantity> <tradeable>`,
+    /// where side is `bid` or `ask`, followed by `id=<id>` when the level has
+    /// an order ID and `ts=<timestamp>` when it has a timestamp. Bids come
+    /// first, then asks, each in book order. Decimals keep their original
+    /// scale, so `from_fixture` restores an identical book. A set symbol or
+    /// currency is written first as `symbol "<value>"` / `currency "<value>"`,
+    /// quoted and escaped like a Rust string literal (`{:?}`) so that empty,
+    /// padded or multi-line values survive the round trip.
+    pub fn to_fixture(&self) -> String {
+        let mut out = String::new();
+        for (key, value) in [("symbol", &self.symbol), ("currency", &self.currency)] {
+            if let Some(value) = value {
+                out.push_str(&format!("{} {:?}\n", key, value));
+            }
+        }
+        for (side, level) in self.iter_levels() {
+            let tag = match side {
+                Side::Buy => "bid",
+                Side::Sell => "ask",
+            };
+            out.push_str(&format!(
+                "{} {} {} {}",
+                tag, level.price, level.quantity, level.tradeable
+            ));
+            if let Some(id) = level.id {
+                out.push_str(&format!(" id={}", id));
+            }
+            if let Some(timestamp) = level.timestamp {
+                out.push_str(&format!(" ts={}", timestamp));
+            }
+            out.push('\n');
+        }
+        out
+    }
+
+    /// Reads a fixture produced by `to_fixture` back into a book.
+    ///
+    /// The fixture is restored as-is; no book validation is applied.
+    pub fn from_fixture(fixture: &str) -> Result<OrderBook, OrderBookError> {
+        let mut book = OrderBook::default();
+        for (index, line) in fixture.lines().enumerate() {
+            let line_no = index + 1;
+            let invalid = |reason: &str| OrderBookError::InvalidFixture(line_no, reason.into());
+
+            let mut fields = line.split_whitespace();
+            let Some(tag) = fields.next() else {
+                continue;
+            };
+            if tag == "symbol" || tag == "currency" {
+                let raw = line.trim_start()[tag.len()..].trim();
+                let value = unquote_fixture_value(raw)
+                    .ok_or_else(|| invalid("metadata value must be a quoted string"))?;
+                let slot = if tag == "symbol" {
+                    &mut book.symbol
+                } else {
+                    &mut book.currency
+                };
+                *slot = Some(value);
+                continue;
+            }
+            let (Some(price), Some(quantity), Some(tradeable)) =
+                (fields.next(), fields.next(), fields.next())
+            else {
+                return Err(invalid("expected `<side> <price> <quantity> <tradeable>`"));
+            };
+            let mut level = Level {
+                price: Decimal::from_str_exact(price)?,
+                quantity: Decimal::from_str_exact(quantity)?,
+                tradeable: tradeable
+                    .parse()
+                    .map_err(|_| invalid("tradeable must be `true` or `false`"))?,
+                id: None,
+                timestamp: None,
+            };
+            for extra in fields {
+                match extra.split_once('=') {
+                    Some(("id", value)) => {
+                        level.id = Some(value.parse().map_err(|_| invalid("invalid id"))?);
+                    }
+                    Some(("ts", value)) => {
+                        level.timestamp =
+                            Some(value.parse().map_err(|_| invalid("invalid timestamp"))?);
+                    }
+                    _ => return Err(invalid("unknown trailing field")),
+                }
+            }
+            match tag {
+                "bid" => book.bids.push(level),
+                "ask" => book.asks.push(level),
+                _ => return Err(invalid("side must be `bid` or `ask`")),
+            }
+        }
+        Ok(book)
+    }
+
+    /// Encodes the book into a compact binary representation.
+    ///
+    /// Decoding with `from_bytes` is much faster than re-running the text parser.
+    /// Encoding failures surface as `OrderBookError::BinaryError`.
+    #[cfg(feature = "binary")]
+    pub fn to_bytes(&self) -> Result<Vec<u8>, OrderBookError> {
+        bincode::serialize(self).map_err(|e| OrderBookError::BinaryError(e.to_string()))
+    }
+
+    /// Decodes a book previously produced by `to_bytes`.
+    #[cfg(feature = "binary")]
+    pub fn from_bytes(bytes: &[u8]) -> Result<OrderBook, OrderBookError> {
+        bincode::deserialize(bytes).map_err(|e| OrderBookError::BinaryError(e.to_string()))
+    }
+
+    /// Executes a Market Order with Partial Fill logic (IOC).
+    ///
+    /// This method mutates the order book by consuming liquidity from the opposite side.
+    /// Levels marked as non-tradeable are left untouched.
+    ///
+    /// # Arguments
+    /// * `side` - The direction of the trade (Buy or Sell).
+    /// * `quantity` - The amount to trade.
+    ///
+    /// # Returns
+    /// * `Ok(Position)` - The resulting position with the weighted average entry price.
+    /// * `Err(OrderBookError)` - If the order is invalid or book is empty, or
+    ///   `Overflow` if the fill's notional exceeds the range of `Decimal` (the
+    ///   book is left unchanged in that case).
+    pub fn execute_market_order(
+        &mut self,
+        side: Side,
+        quantity: Decimal,
+    ) -> Result<Position, OrderBookError> {
+        self.execute_market_order_with_policy(side, quantity, MatchPolicy::Fifo)
+    }
+
+    /// Executes a Market Order like `execute_market_order`, allocating fills
+    /// within a price level according to `policy`.
+    ///
+    /// Levels are aggregated, so each price holds a single quantity and only
+    /// `MatchPolicy::Fifo` is meaningful.
+    ///
+    /// # Errors
+    /// * `Unsupported` - for `MatchPolicy::ProRata`, until levels carry
+    ///   individual resting orders. The book is left unchanged.
+    /// * Otherwise the same errors as `execute_market_order`.
+    pub fn execute_market_order_with_policy(
+        &mut self,
+        side: Side,
+        quantity: Decimal,
+        policy: MatchPolicy,
+    ) -> Result<Position, OrderBookError> {
+        if policy == MatchPolicy::ProRata {
+            return Err(OrderBookError::Unsupported(
+                "pro-rata matching needs individual resting orders".into(),
+            ));
+        }
+
+        if quantity <= Decimal::ZERO {
+            return Err(OrderBookError::NotEnoughLiquidity(
+                quantity,
+                self.liquidity(side),
+            ));
+        }
+
+        let levels = match side {
+            Side::Buy => &mut self.asks,
+            Side::Sell => &mut self.bids,
+        };
+
+        let (filled_qty, total_cost) = fill_against(levels, quantity)?;
+        if filled_qty == Decimal::ZERO {
+            return Err(OrderBookError::NotEnoughLiquidity(
+                quantity,
+                self.liquidity(side),
+            ));
+        }
+        // A VWAP never exceeds the highest price filled, so this cannot fail
+        // once `fill_against` has succeeded.
+        let avg_price = total_cost
+            .checked_div(filled_qty)
+            .ok_or(OrderBookError::Overflow)?;
+
+        Ok(Position {
+            side,
+            quantity: filled_qty,
+            entry_price: avg_price,
+        })
+    }
+
+    /// Executes a fill-or-kill market order: all of `quantity` or nothing.
+    ///
+    /// Unlike `execute_market_order`, a partial fill is an error. If the
+    /// tradeable liquidity on the consumed side is smaller than `quantity`,
+    /// `NotEnoughLiquidity(quantity, available)` is returned and the book is
+    /// left unchanged.
+    pub fn execute_market_order_strict(
+        &mut self,
+        side: Side,
+        quantity: Decimal,
+    ) -> Result<Position, OrderBookError> {
+        let available = self.liquidity(side);
+        if available < quantity {
+            return Err(OrderBookError::NotEnoughLiquidity(quantity, available));
+        }
+        self.execute_market_order(side, quantity)
+    }
+
+    /// Executes a sequence of market orders against the evolving book.
+    ///
+    /// Each `(side, quantity)` pair is passed to `execute_market_order` in
+    /// order, so later orders see the liquidity left by earlier ones. A failing
+    /// order does not stop the replay; every order gets its own result.
+    pub fn replay(&mut self, orders: &[(Side, Decimal)]) -> Vec<Result<Position, OrderBookError>> {
+        orders
+            .iter()
+            .map(|&(side, quantity)| self.execute_market_order(side, quantity))
+            .collect()
+    }
+}
+
+impl FromStr for OrderBook {
+    type Err = OrderBookError;
+
+    /// Parses a snapshot without instrument validation (see `parse_order_book`).
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_order_book(s, None)
+    }
+}
+
+impl fmt::Display for OrderBook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Order Book:")?;
+        writeln!(
+            f,
+            "  ASKS (Top): {:?}",
+            self.asks.iter().take(3).collect::<Vec<_>>()
+        )?;
+        writeln!(
+            f,
+            "  BIDS (Top): {:?}",
+            self.bids.iter().take(3).collect::<Vec<_>>()
+        )
+    }
+}
+
+/// Parses a raw string input into an `OrderBook` struct.
+///
+/// Validates the structure against the grammar and business rules (Instrument Config).
+/// A leading UTF-8 byte order mark and surrounding whitespace (including
+/// `\r\n` line endings) are stripped before parsing.
+///
+/// # Arguments
+/// * `input` - The string snapshot (e.g., "BIDS:100,1;ASKS:101,1").
+/// * `config` - Optional instrument configuration for strict validation.
+pub fn parse_order_book(
+    input: &str,
+    config: Option<&InstrumentConfig>,
+) -> Result<OrderBook, OrderBookError> {
+    let mut book = OrderBook::default();
+    parse_order_book_into(input, &mut book, config)?;
+    Ok(book)
+}
+
+/// Parses a strictly canonical snapshot without going through the pest grammar.
+///
+/// Small snapshots are dominated by the grammar's fixed per-parse overhead.
+/// This fast path handles the canonical form `BIDS:price,qty|...;ASKS:...`
+/// (optional per-level order IDs allowed) with plain string splitting. Any
+/// deviation — whitespace, a BOM, digit-group underscores, a checksum trailer,
+/// a one-sided book or malformed syntax — falls back to `parse_order_book`,
+/// so results and errors are identical to it in every case. No instrument
+/// config is applied.
+pub fn parse_order_book_fast(input: &str) -> Result<OrderBook, OrderBookError> {
+    let Some((bids, asks)) = scan_canonical(input) else {
+        return parse_order_book(input, None);
+    };
+
+    let mut book = OrderBook::with_capacity(bids.len(), asks.len());
+    let mut spans = LevelSpans::default();
+    for (raw_levels, levels, side_spans) in [
+        (bids, &mut book.bids, &mut spans.bids),
+        (asks, &mut book.asks, &mut spans.asks),
+    ] {
+        for raw in raw_levels {
+            let id = raw.id.map(parse_order_id).transpose()?;
+            levels.push(Level {
+                id,
+                ..Level::new_unchecked(parse_decimal(raw.price)?, parse_decimal(raw.quantity)?)
+            });
+            side_spans.push(raw.span);
+        }
+    }
+
+    finish_order_book(
+        &mut book,
+        spans,
+        None,
+        None,
+        &ParseOptions::default(),
+        &mut Vec::new(),
+    )?;
+    Ok(book)
+}
+
+/// Parses every input independently, collecting one result per input.
+///
+/// Unlike looping with `?`, a failing snapshot does not stop the rest, so the
+/// caller can tally successes and failures. Results are in input order.
+pub fn parse_many(
+    inputs: &[&str],
+    config: Option<&InstrumentConfig>,
+) -> Vec<Result<OrderBook, OrderBookError>> {
+    inputs
+        .iter()
+        .map(|input| parse_order_book(input, config))
+        .collect()
+}
+
+/// Parses several snapshots separated by one or more blank lines.
+///
+/// Each chunk between blank lines is parsed as one snapshot with
+/// `parse_order_book`; leading and trailing blank lines are ignored. Parsing
+/// stops at the first failing chunk, reported as `SnapshotError` with the
+/// chunk's 0-based index.
+pub fn parse_order_book_multi(input: &str) -> Result<Vec<OrderBook>, OrderBookError> {
+    let mut books = Vec::new();
+    let mut chunk: Vec<&str> = Vec::new();
+    for line in input.lines().chain(core::iter::once("")) {
+        if !line.trim().is_empty() {
+            chunk.push(line);
+            continue;
+        }
+        if chunk.is_empty() {
+            continue;
+        }
+        let book = parse_order_book(&chunk.join("\n"), None)
+            .map_err(|err| OrderBookError::SnapshotError(books.len(), Box::new(err)))?;
+        books.push(book);
+        chunk.clear();
+    }
+    Ok(books)
+}
+
+/// Parses a snapshot into an existing `book`, reusing its allocations.
+///
+/// The book is cleared and refilled, so replaying many snapshots through the
+/// same book avoids allocating new level vectors each time. Accepts the same
+/// input and reports the same errors as `parse_order_book`; after an error the
+/// contents of `book` are unspecified.
+pub fn parse_order_book_into(
+    input: &str,
+    book: &mut OrderBook,
+    config: Option<&InstrumentConfig>,
+) -> Result<(), OrderBookError> {
+    fill_order_book(
+        input,
+        config,
+        &ParseOptions::default(),
+        book,
+        &mut Vec::new(),
+    )
+}
+
+/// Parses a gzip-compressed snapshot read from `reader`.
+///
+/// The stream is decompressed with `flate2::read::GzDecoder` and handed to
+/// `parse_order_book_from_reader`. Corrupt or truncated archives surface as
+/// `OrderBookError::IoError`.
+#[cfg(feature = "gzip")]
+pub fn parse_order_book_gz<R: Read>(
+    reader: R,
+    config: Option<&InstrumentConfig>,
+) -> Result<OrderBook, OrderBookError> {
+    parse_order_book_from_reader(flate2::read::GzDecoder::new(reader), config)
+}
+
+/// Writes each book as one JSON object per line (JSON Lines).
+///
+/// The writer is flushed after every line, so a consumer tailing the output
+/// sees complete records. Stops at the first failure: encoding errors are
+/// reported as `JsonError`, write errors as `IoError`.
+#[cfg(feature = "json")]
+pub fn write_jsonl<W: Write>(
+    books: impl Iterator<Item = OrderBook>,
+    mut writer: W,
+) -> Result<(), OrderBookError> {
+    for book in books {
+        serde_json::to_writer(&mut writer, &book)
+            .map_err(|e| OrderBookError::JsonError(e.to_string()))?;
+        writer.write_all(b"\n")?;
+        writer.flush()?;
+    }
+    Ok(())
+}
+
+/// Parses an exchange JSON depth snapshot in the array-of-arrays shape.
+///
+/// Accepts the format used by Binance, OKX and others:
+/// `{"bids":[["100.0","10"],...],"asks":[["101.0","5"],...]}`. Prices and
+/// quantities may be JSON strings or numbers; any further entries in a level
+/// array and any other top-level fields are ignored. The levels then go
+/// through the same validation as `parse_order_book`.
+///
+/// Numbers in exponent form (`1e-7`) are accepted. Malformed JSON or level
+/// entries are reported as `JsonError`; a non-positive price is reported as
+/// `NonPositiveValue` and a negative quantity as `NegativeQuantity`.
+#[cfg(feature = "json")]
+pub fn parse_order_book_json(
+    json: &str,
+    config: Option<&InstrumentConfig>,
+) -> Result<OrderBook, OrderBookError> {
+    #[derive(serde::Deserialize)]
+    struct JsonSnapshot {
+        bids: Vec<Vec<serde_json::Value>>,
+        asks: Vec<Vec<serde_json::Value>>,
+    }
+
+    fn json_decimal(value: Option<&serde_json::Value>) -> Result<Decimal, OrderBookError> {
+        let raw = match value {
+            Some(serde_json::Value::String(raw)) => raw.clone(),
+            Some(serde_json::Value::Number(number)) => number.to_string(),
+            other => {
+                return Err(OrderBookError::JsonError(format!(
+                    "expected a price or quantity, got {:?}",
+                    other
+                )));
+            }
+        };
+        let parsed = if raw.contains(['e', 'E']) {
+            Decimal::from_scientific(&raw)
+        } else {
+            Decimal::from_str_exact(&raw)
+        };
+        parsed.map_err(|e| OrderBookError::JsonError(format!("invalid number {:?}: {}", raw, e)))
+    }
+
+    fn json_levels(raw: &[Vec<serde_json::Value>]) -> Result<Vec<Level>, OrderBookError> {
+        raw.iter()
+            .map(|entry| {
+                let price = json_decimal(entry.first())?;
+                let quantity = json_decimal(entry.get(1))?;
+                // The text grammar has no sign, so reject what it could never produce.
+                if price <= Decimal::ZERO {
+                    return Err(OrderBookError::NonPositiveValue(price));
+                }
+                if quantity < Decimal::ZERO {
+                    return Err(OrderBookError::NegativeQuantity(quantity));
+                }
+                Ok(Level::new_unchecked(price, quantity))
+            })
+            .collect()
+    }
+
+    let snapshot: JsonSnapshot =
+        serde_json::from_str(json).map_err(|e| OrderBookError::JsonError(e.to_string()))?;
+    let mut book = OrderBook {
+        bids: json_levels(&snapshot.bids)?,
+        asks: json_levels(&snapshot.asks)?,
+        ..OrderBook::default()
+    };
+    finish_order_book(
+        &mut book,
+        LevelSpans::default(),
+        None,
+        config,
+        &ParseOptions::default(),
+        &mut Vec::new(),
+    )?;
+    Ok(book)
+}
+
+/// Runs the grammar over `input` and returns the raw pest pairs for `Rule::order_book`.
+///
+/// No `OrderBook` is built and no validation is performed. The input is used
+/// exactly as given (no trimming), so every span indexes directly into
+/// `input`, which makes this suitable for tooling that annotates the source.
+pub fn parse_raw(input: &str) -> Result<pest::iterators::Pairs<'_, Rule>, OrderBookError> {
+    Ok(OrderBookParser::parse(Rule::order_book, input)?)
+}
+
+/// Reads a snapshot from any `Read` source and parses it into an `OrderBook`.
+///
+/// The whole input is buffered and passed to `parse_order_book`, which takes
+/// care of a leading byte order mark and surrounding whitespace.
+#[cfg(feature = "std")]
+pub fn parse_order_book_from_reader<R: Read>(
+    mut reader: R,
+    config: Option<&InstrumentConfig>,
+) -> Result<OrderBook, OrderBookError> {
+    let mut input = String::new();
+    reader.read_to_string(&mut input)?;
+    parse_order_book(&input, config)
+}
+
+/// Parses a raw string input into an `OrderBook` struct using custom `ParseOptions`.
+///
+/// Behaves like `parse_order_book`, with the extra checks enabled in `options`.
+pub fn parse_order_book_with_options(
+    input: &str,
+    config: Option<&InstrumentConfig>,
+    options: &ParseOptions,
+) -> Result<OrderBook, OrderBookError> {
+    let mut book = OrderBook::default();
+    fill_order_book(input, config, options, &mut book, &mut Vec::new())?;
+    Ok(book)
+}
+
+/// Parses a snapshot, collecting downgraded validation issues as warnings.
+///
+/// Issues enabled by `ParseOptions::crossed_as_warning` and
+/// `ParseOptions::unsorted_as_warning` are returned alongside the book
+/// instead of aborting the parse. All other problems remain hard errors.
+pub fn parse_order_book_with_warnings(
+    input: &str,
+    config: Option<&InstrumentConfig>,
+    options: &ParseOptions,
+) -> Result<(OrderBook, Vec<OrderBookWarning>), OrderBookError> {
+    let mut book = OrderBook::default();
+    let mut warnings = Vec::new();
+    fill_order_book(input, config, options, &mut book, &mut warnings)?;
+    Ok((book, warnings))
+}
+
+/// Delimiters used by a feed's text format.
+///
+/// The pest grammar is fixed at compile time to the canonical delimiters
+/// (`BIDS:price,qty|price,qty;ASKS:...`). Other feeds are supported by
+/// rewriting their input into the canonical form with `normalize` before
+/// parsing. Supported profiles:
+///
+/// * `DelimiterProfile::CANONICAL` - `BIDS:100,1|99,2;ASKS:101,1`
+/// * `DelimiterProfile::SPACE_SEPARATED` - `BIDS:100,1 99,2;ASKS:101,1`
+///   (levels separated by any run of whitespace).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DelimiterProfile {
+    /// Separates the side identifier from its levels.
+    pub header: char,
+    /// Separates the bids section from the asks section.
+    pub side: char,
+    /// Separates levels within a side. `' '` means any run of whitespace.
+    pub level: char,
+    /// Separates the fields (price, quantity, id) of a level.
+    pub field: char,
+}
+
+impl DelimiterProfile {
+    /// The delimiters understood natively by the grammar.
+    pub const CANONICAL: DelimiterProfile = DelimiterProfile {
+        header: ':',
+        side: ';',
+        level: '|',
+        field: ',',
+    };
+
+    /// Levels separated by whitespace, sides by semicolons.
+    pub const SPACE_SEPARATED: DelimiterProfile = DelimiterProfile {
+        header: ':',
+        side: ';',
+        level: ' ',
+        field: ',',
+    };
+
+    /// Rewrites `input` written in this profile into the canonical grammar form.
+    ///
+    /// Sections that do not contain the header delimiter are passed through
+    /// unchanged so the grammar can report them.
+    pub fn normalize(&self, input: &str) -> String {
+        if *self == Self::CANONICAL {
+            return input.to_string();
+        }
+
+        let sections: Vec<String> = input
+            .split(self.side)
+            .map(|section| {
+                let Some((header, body)) = section.split_once(self.header) else {
+                    return section.to_string();
+                };
+                let levels: Vec<&str> = if self.level == ' ' {
+                    body.split_whitespace().collect()
+                } else {
+                    body.split(self.level)
+                        .map(str::trim)
+                        .filter(|level| !level.is_empty())
+                        .collect()
+                };
+                let levels: Vec<String> = levels
+                    .iter()
+                    .map(|level| level.split(self.field).collect::<Vec<_>>().join(","))
+                    .collect();
+                format!("{}:{}", header.trim(), levels.join("|"))
+            })
+            .collect();
+        sections.join(";")
+    }
+
+    /// Formats `book` as text in this profile.
+    pub fn render(&self, book: &OrderBook) -> String {
+        let side_text = |levels: &[Level]| -> String {
+            let separator = self.level.to_string();
+            levels
+                .iter()
+                .map(|level| {
+                    let mut text = format!("{}{}{}", level.price, self.field, level.quantity);
+                    if let Some(id) = level.id {
+                        text.push_str(&format!("{}{}", self.field, id));
+                    }
+                    text
+                })
+                .collect::<Vec<_>>()
+                .join(&separator)
+        };
+        format!(
+            "BIDS{h}{}{s}ASKS{h}{}",
+            side_text(&book.bids),
+            side_text(&book.asks),
+            h = self.header,
+            s = self.side,
+        )
+    }
+}
+
+/// Parses input written with a non-canonical `DelimiterProfile`.
+///
+/// The input is normalized into the canonical form first, then parsed and
+/// validated exactly like `parse_order_book`.
+pub fn parse_order_book_with_profile(
+    input: &str,
+    profile: &DelimiterProfile,
+    config: Option<&InstrumentConfig>,
+) -> Result<OrderBook, OrderBookError> {
+    parse_order_book(&profile.normalize(input), config)
+}
+
+/// Parses and validates `input`, writing the levels into `book`.
+///
+/// Both sides of `book` are cleared first, keeping their allocated capacity.
+fn fill_order_book(
+    input: &str,
+    config: Option<&InstrumentConfig>,
+    options: &ParseOptions,
+    book: &mut OrderBook,
+    warnings: &mut Vec<OrderBookWarning>,
+) -> Result<(), OrderBookError> {
+    let stripped;
+    let input = if options.strip_comments {
+        stripped = strip_comment_lines(input.strip_prefix('\u{FEFF}').unwrap_or(input));
+        stripped.as_str()
+    } else {
+        input
+    };
+    let content = input.strip_prefix('\u{FEFF}').unwrap_or(input).trim_start();
+    let offset = input.len() - content.len();
+    let content = content.trim_end();
+    let mut parsed = parse_raw(content)?;
+    let root = parsed
+        .next()
+        .ok_or_else(|| OrderBookError::MissingSection("Empty input".into()))?;
+    // The root rule does not anchor to the end of input, so a valid book can
+    // be followed by text the grammar never looked at.
+    let trailing = &content[root.as_span().end()..];
+    if !trailing.is_empty() {
+        return Err(OrderBookError::TrailingData(trailing.to_string()));
+    }
+
+    book.clear();
+
+    let mut spans = LevelSpans::default();
+    let mut declared_checksum = None;
+    for record in root.into_inner() {
+        if options.strict_whitespace {
+            reject_whitespace(&record, offset)?;
+        }
+        match record.as_rule() {
+            Rule::bids_side => {
+                parse_levels(record, options, &mut book.bids, &mut spans.bids, offset)?
+            }
+            Rule::asks_side => {
+                parse_levels(record, options, &mut book.asks, &mut spans.asks, offset)?
+            }
+            Rule::checksum => declared_checksum = Some(parse_checksum(record)?),
+            _ => {}
+        }
+    }
+
+    finish_order_book(book, spans, declared_checksum, config, options, warnings)
+}
+
+/// Fails with `UnexpectedWhitespace` if the text of `pair` contains any whitespace.
+///
+/// Trailing whitespace is ignored: a repetition such as `level_list` can end
+/// its span after the implicit whitespace that precedes the next section.
+fn reject_whitespace(
+    pair: &pest::iterators::Pair<Rule>,
+    offset: usize,
+) -> Result<(), OrderBookError> {
+    match pair.as_str().trim_end().find(char::is_whitespace) {
+        Some(position) => Err(OrderBookError::UnexpectedWhitespace(
+            offset + pair.as_span().start() + position,
+        )),
+        None => Ok(()),
+    }
+}
+
+/// Removes blank lines and full-line `#` comments, keeping the remaining lines.
+fn strip_comment_lines(input: &str) -> String {
+    input
+        .lines()
+        .filter(|line| {
+            let line = line.trim_start();
+            !line.is_empty() && !line.starts_with('#')
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+/// Merges runs of adjacent levels with equal prices, keeping `spans` aligned.
+///
+/// Fails with `OrderBookError::Overflow` if a merged quantity exceeds the
+/// range of `Decimal`.
+fn aggregate_adjacent(
+    levels: &mut Vec<Level>,
+    spans: &mut Vec<(usize, usize)>,
+) -> Result<(), OrderBookError> {
+    if spans.len() == levels.len() {
+        let mut index = 0;
+        spans.retain(|_| {
+            let keep = index == 0 || levels[index - 1].price != levels[index].price;
+            index += 1;
+            keep
+        });
+    }
+    let mut overflow = false;
+    levels.dedup_by(|later, kept| {
+        if later.price != kept.price {
+            return false;
+        }
+        match kept.quantity.checked_add(later.quantity) {
+            Some(quantity) => kept.quantity = quantity,
+            None => overflow = true,
+        }
+        kept.id = None;
+        true
+    });
+    if overflow {
+        return Err(OrderBookError::Overflow);
+    }
+    Ok(())
+}
+
+/// Validates freshly parsed levels: the empty-book and checksum checks,
+/// optional scaling, snapping and aggregation, then the logical, option and
+/// instrument rules.
+fn finish_order_book(
+    book: &mut OrderBook,
+    mut spans: LevelSpans,
+    declared_checksum: Option<u32>,
+    config: Option<&InstrumentConfig>,
+    options: &ParseOptions,
+    warnings: &mut Vec<OrderBookWarning>,
+) -> Result<(), OrderBookError> {
+    if book.is_empty() && !options.allow_empty {
+        return Err(OrderBookError::MissingSection("Empty book".into()));
+    }
+
+    if let Some(declared) = declared_checksum {
+        let computed = book.crc32_top(CHECKSUM_DEPTH);
+        if declared != computed {
+            return Err(OrderBookError::ChecksumMismatch(declared, computed));
+        }
+    }
+
+    if let Some(cfg) = config {
+        scale_to_instrument(book, cfg)?;
+    }
+
+    if let (true, Some(cfg)) = (options.round_to_tick, config) {
+        snap_to_instrument(book, cfg)?;
+    }
+
+    if options.aggregate_duplicates {
+        aggregate_adjacent(&mut book.bids, &mut spans.bids)?;
+        aggregate_adjacent(&mut book.asks, &mut spans.asks)?;
+    }
+
+    validate_book_logic(book, &spans, options, warnings)?;
+    validate_options(book, &options.validation)?;
+    if let Some(cfg) = config {
+        validate_instrument_rules(book, cfg)?;
+    }
+
+    Ok(())
+}
+
+/// The text of one level located by `scan_canonical`, not yet converted.
+struct RawLevel<'a> {
+    price: &'a str,
+    quantity: &'a str,
+    id: Option<&'a str>,
+    span: (usize, usize),
+}
+
+/// Splits a canonical snapshot into its raw levels.
+///
+/// Returns `None` as soon as the input deviates from the strict canonical form
+/// so the caller can defer to the grammar. The whole input is checked before
+/// any number is converted, keeping error precedence identical to the pest path.
+fn scan_canonical(input: &str) -> Option<(Vec<RawLevel<'_>>, Vec<RawLevel<'_>>)> {
+    const BIDS: &str = "BIDS:";
+    const ASKS: &str = ";ASKS:";
+
+    let (bids, asks) = input.strip_prefix(BIDS)?.split_once(ASKS)?;
+    let asks_offset = BIDS.len() + bids.len() + ASKS.len();
+    Some((
+        scan_level_list(bids, BIDS.len())?,
+        scan_level_list(asks, asks_offset)?,
+    ))
+}
+
+fn scan_level_list(list: &str, mut offset: usize) -> Option<Vec<RawLevel<'_>>> {
+    let mut levels = Vec::new();
+    if list.is_empty() {
+        return Some(levels);
+    }
+    for part in list.split('|') {
+        let mut fields = part.split(',');
+        let (price, quantity) = (fields.next()?, fields.next()?);
+        let id = fields.next();
+        if fields.next().is_some()
+            || !is_canonical_number(price)
+            || !is_canonical_number(quantity)
+            || !id.is_none_or(is_canonical_digits)
+        {
+            return None;
+        }
+        levels.push(RawLevel {
+            price,
+            quantity,
+            id,
+            span: (offset, offset + part.len()),
+        });
+        offset += part.len() + 1;
+    }
+    Some(levels)
+}
+
+fn is_canonical_digits(raw: &str) -> bool {
+    !raw.is_empty() && raw.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+fn is_canonical_number(raw: &str) -> bool {
+    match raw.split_once('.') {
+        Some((whole, fraction)) => is_canonical_digits(whole) && is_canonical_digits(fraction),
+        None => is_canonical_digits(raw),
+    }
+}
+
+/// Byte ranges of each parsed level, indexed like the book's sides.
+#[derive(Default)]
+struct LevelSpans {
+    bids: Vec<(usize, usize)>,
+    asks: Vec<(usize, usize)>,
+}
+
+fn parse_levels(
+    pair: pest::iterators::Pair<Rule>,
+    options: &ParseOptions,
+    levels: &mut Vec<Level>,
+    spans: &mut Vec<(usize, usize)>,
+    offset: usize,
+) -> Result<(), OrderBookError> {
+    for inner in pair.into_inner() {
+        if inner.as_rule() == Rule::level_list {
+            for level_pair in inner.into_inner() {
+                if level_pair.as_rule() == Rule::level {
+                    let span = level_pair.as_span();
+                    spans.push((offset + span.start(), offset + span.end()));
+                    // Each number is paired with whether a `%` followed it.
+                    let mut fields: [Option<(&str, bool)>; 2] = [None, None];
+                    let mut count = 0;
+                    let mut id = None;
+                    for token in level_pair.into_inner() {
+                        match token.as_rule() {
+                            Rule::number => {
+                                if let Some(field) = fields.get_mut(count) {
+                                    *field = Some((token.as_str(), false));
+                                }
+                                count += 1;
+                            }
+                            Rule::percent => {
+                                let previous = count.checked_sub(1).and_then(|i| fields.get_mut(i));
+                                if let Some(Some((_, percent))) = previous {
+                                    *percent = true;
+                                }
+                            }
+                            Rule::integer => id = Some(parse_order_id(token.as_str())?),
+                            _ => {}
+                        }
+                    }
+                    let first = fields[0]
+                        .ok_or_else(|| OrderBookError::MissingSection("Missing price".into()))?;
+                    let second = fields[1]
+                        .ok_or_else(|| OrderBookError::MissingSection("Missing quantity".into()))?;
+                    let ((price_str, price_percent), (qty_str, qty_percent)) =
+                        match options.level_order {
+                            LevelOrder::PriceFirst => (first, second),
+                            LevelOrder::QuantityFirst => (second, first),
+                        };
+                    if price_percent {
+                        return Err(OrderBookError::InvalidQuantityFormat(format!(
+                            "price {} cannot be a percentage",
+                            price_str
+                        )));
+                    }
+                    let quantity = parse_decimal(qty_str)?;
+                    let quantity = match (options.quantity_format, qty_percent) {
+                        (QuantityFormat::Absolute, false) => quantity,
+                        (QuantityFormat::Percent, true) => quantity / Decimal::ONE_HUNDRED,
+                        (QuantityFormat::Absolute, true) => {
+                            return Err(OrderBookError::InvalidQuantityFormat(format!(
+                                "percentage quantity {}% requires QuantityFormat::Percent",
+                                qty_str
+                            )));
+                        }
+                        (QuantityFormat::Percent, false) => {
+                            return Err(OrderBookError::InvalidQuantityFormat(format!(
+                                "quantity {} is missing its '%' suffix",
+                                qty_str
+                            )));
+                        }
+                    };
+                    levels.push(Level {
+                        id,
+                        ..Level::new_unchecked(parse_decimal(price_str)?, quantity)
+                    });
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Converts a grammar `number` token into a Decimal, dropping digit-group underscores.
+fn parse_decimal(raw: &str) -> Result<Decimal, OrderBookError> {
+    if raw.contains('_') {
+        Ok(Decimal::from_str_exact(&raw.replace('_', ""))?)
+    } else {
+        Ok(Decimal::from_str_exact(raw)?)
+    }
+}
+
+fn parse_checksum(pair: pest::iterators::Pair<Rule>) -> Result<u32, OrderBookError> {
+    let raw = pair.into_inner().as_str();
+    raw.replace('_', "")
+        .parse()
+        .map_err(|_| OrderBookError::InvalidChecksum(raw.to_string()))
+}
+
+/// Standard CRC-32 (IEEE 802.3, reflected polynomial `0xEDB88320`).
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for &byte in bytes {
+        crc ^= u32::from(byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Feeds `bytes` into a running FNV-1a (64-bit) hash.
+fn fnv1a(mut hash: u64, bytes: &[u8]) -> u64 {
+    for &byte in bytes {
+        hash ^= u64::from(byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+    }
+    hash
+}
+
+/// Reverses the `{:?}` quoting `OrderBook::to_fixture` applies to metadata.
+///
+/// Returns `None` if `raw` is not a double-quoted string or contains an
+/// unknown escape.
+fn unquote_fixture_value(raw: &str) -> Option<String> {
+    let inner = raw.strip_prefix('"')?.strip_suffix('"')?;
+    let mut out = String::with_capacity(inner.len());
+    let mut chars = inner.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return None,
+            '\\' => {}
+            c => {
+                out.push(c);
+                continue;
+            }
+        }
+        let unescaped = match chars.next()? {
+            'n' => '\n',
+            'r' => '\r',
+            't' => '\t',
+            '0' => '\0',
+            c @ ('\\' | '"' | '\'') => c,
+            'u' => {
+                let (hex, rest) = chars.as_str().strip_prefix('{')?.split_once('}')?;
+                chars = rest.chars();
+                char::from_u32(u32::from_str_radix(hex, 16).ok()?)?
+            }
+            _ => return None,
+        };
+        out.push(unescaped);
+    }
+    Some(out)
+}
+
+/// Converts a grammar `integer` token into an order ID.
+fn parse_order_id(raw: &str) -> Result<u64, OrderBookError> {
+    raw.replace('_', "")
+        .parse()
+        .map_err(|_| OrderBookError::InvalidOrderId(raw.to_string()))
+}
+
+/// Multiplies parsed values by the config's `price_scale` and `qty_scale`, if set.
+///
+/// Fails with `OrderBookError::Overflow` if a scaled value exceeds the range
+/// of `Decimal`.
+fn scale_to_instrument(
+    book: &mut OrderBook,
+    config: &InstrumentConfig,
+) -> Result<(), OrderBookError> {
+    if config.price_scale.is_none() && config.qty_scale.is_none() {
+        return Ok(());
+    }
+    for level in book.bids.iter_mut().chain(book.asks.iter_mut()) {
+        if let Some(scale) = config.price_scale {
+            level.price = level
+                .price
+                .checked_mul(scale)
+                .ok_or(OrderBookError::Overflow)?;
+        }
+        if let Some(scale) = config.qty_scale {
+            level.quantity = level
+                .quantity
+                .checked_mul(scale)
+                .ok_or(OrderBookError::Overflow)?;
+        }
+    }
+    Ok(())
+}
+
+/// Rounds every price to the tick size and every quantity to the lot step.
+///
+/// Fails with `OrderBookError::Overflow` if a value cannot be rounded within
+/// the range of `Decimal`.
+fn snap_to_instrument(
+    book: &mut OrderBook,
+    config: &InstrumentConfig,
+) -> Result<(), OrderBookError> {
+    for level in book.bids.iter_mut().chain(book.asks.iter_mut()) {
+        level.price =
+            snap_to_step(level.price, config.tick_size).ok_or(OrderBookError::Overflow)?;
+        level.quantity =
+            snap_to_step(level.quantity, config.lot_step).ok_or(OrderBookError::Overflow)?;
+    }
+    Ok(())
+}
+
+/// Rounds `value` to the nearest multiple of `step` (halves round away from
+/// zero), or `None` if the division or multiplication overflows.
+fn snap_to_step(value: Decimal, step: Decimal) -> Option<Decimal> {
+    if step.is_zero() {
+        return Some(value);
+    }
+    value
+        .checked_div(step)?
+        .round_dp_with_strategy(0, RoundingStrategy::MidpointAwayFromZero)
+        .checked_mul(step)
+}
+
+/// Renders the suggestion of `OrderBookError::InvalidTickSize`, if there is one.
+struct DidYouMean(Option<Decimal>);
+
+impl fmt::Display for DidYouMean {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Some(suggestion) => write!(f, " (did you mean {}?)", suggestion),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Infers a tick size as the GCD of the gaps between adjacent prices.
+fn infer_tick(prices: impl IntoIterator<Item = Decimal>) -> Option<Decimal> {
+    let mut tick: Option<Decimal> = None;
+    let mut previous: Option<Decimal> = None;
+    for price in prices {
+        let Some(last) = previous.replace(price) else {
+            continue;
+        };
+        let gap = (last - price).abs();
+        if gap.is_zero() {
+            continue;
+        }
+        tick = Some(match tick {
+            None => gap,
+            Some(current) => decimal_gcd(current, gap)?,
+        });
+    }
+    tick
+}
+
+/// Greatest common divisor of two non-negative decimals.
+///
+/// Both values are scaled to integers at a common scale before running Euclid's
+/// algorithm. Returns `None` if the scaled mantissas overflow.
+fn decimal_gcd(a: Decimal, b: Decimal) -> Option<Decimal> {
+    let scale = a.scale().max(b.scale());
+    let to_units = |value: Decimal| -> Option<i128> {
+        10i128
+            .checked_pow(scale - value.scale())?
+            .checked_mul(value.mantissa().abs())
+    };
+    let (mut x, mut y) = (to_units(a)?, to_units(b)?);
+    while y != 0 {
+        (x, y) = (y, x % y);
+    }
+    Decimal::try_from_i128_with_scale(x, scale)
+        .ok()
+        .map(|gcd| gcd.normalize())
+}
+
+/// Walks the tradeable levels of a side, filling up to `quantity` without mutating it.
+///
+/// Returns the fill VWAP and the quantity actually filled, or `None` if nothing
+/// could be filled or the notional overflows.
+fn simulate_fill(levels: &[Level], quantity: Decimal) -> Option<(Decimal, Decimal)> {
+    let (total_cost, filled) = fill_cost(levels, quantity).ok()?;
+    if filled <= Decimal::ZERO {
+        return None;
+    }
+    Some((total_cost.checked_div(filled)?, filled))
+}
+
+/// Total cost and filled quantity of taking up to `quantity` from the
+/// tradeable levels of a side, using checked arithmetic.
+fn fill_cost(levels: &[Level], quantity: Decimal) -> Result<(Decimal, Decimal), OrderBookError> {
+    let mut remaining = quantity;
+    let mut total_cost = Decimal::ZERO;
+    for level in levels.iter().filter(|level| level.tradeable) {
+        if remaining <= Decimal::ZERO {
+            break;
+        }
+        let fill = level.quantity.min(remaining);
+        total_cost = level
+            .price
+            .checked_mul(fill)
+            .and_then(|cost| total_cost.checked_add(cost))
+            .ok_or(OrderBookError::Overflow)?;
+        remaining -= fill;
+    }
+    Ok((total_cost, quantity - remaining))
+}
+
+/// Consumes up to `quantity` from the tradeable levels of a side, returning
+/// `(filled, cost)`.
+///
+/// The fill is priced with `fill_cost` first, so this and `simulate_fill`
+/// walk the levels identically, and an `Overflow` leaves `levels` untouched.
+/// A level whose quantity exactly matches the remainder is removed and the
+/// walk stops there, without touching any later level at the same price.
+fn fill_against(
+    levels: &mut Vec<Level>,
+    quantity: Decimal,
+) -> Result<(Decimal, Decimal), OrderBookError> {
+    let (cost, filled) = fill_cost(levels, quantity)?;
+
+    let mut remaining = filled;
+    let mut i = 0;
+    while i < levels.len() && remaining > Decimal::ZERO {
+        let level = &mut levels[i];
+
+        if !level.tradeable {
+            i += 1;
+            continue;
+        }
+
+        if level.quantity <= remaining {
+            remaining -= level.quantity;
+            levels.remove(i);
+        } else {
+            level.quantity -= remaining;
+            remaining = Decimal::ZERO;
+            i += 1;
+        }
+    }
+
+    Ok((filled, cost))
+}
+
+/// The tradeable levels of a side, in book order.
+fn tradeable_levels(levels: &[Level]) -> impl Iterator<Item = &Level> + Clone {
+    levels.iter().filter(|level| level.tradeable)
+}
+
+/// Sum of the quantities of `levels`, or `None` if it overflows.
+fn checked_quantity<'a>(levels: impl IntoIterator<Item = &'a Level>) -> Option<Decimal> {
+    levels.into_iter().try_fold(Decimal::ZERO, |total, level| {
+        total.checked_add(level.quantity)
+    })
+}
+
+/// Sum of `price * quantity` over `levels`, or `None` if it overflows.
+fn checked_notional<'a>(levels: impl IntoIterator<Item = &'a Level>) -> Option<Decimal> {
+    levels.into_iter().try_fold(Decimal::ZERO, |total, level| {
+        total.checked_add(level.price.checked_mul(level.quantity)?)
+    })
+}
+
+/// Binary-searches a sorted side for `price`.
+///
+/// Bids (`Side::Buy`) are sorted descending and asks (`Side::Sell`) ascending.
+/// Returns `Ok(index)` of the matching level, or `Err(index)` of the position
+/// where a level with that price would be inserted.
+fn search_level(levels: &[Level], side: Side, price: Decimal) -> Result<usize, usize> {
+    match side {
+        Side::Buy => levels.binary_search_by(|level| price.cmp(&level.price)),
+        Side::Sell => levels.binary_search_by(|level| level.price.cmp(&price)),
+    }
+}
+
+/// Appends the changes between two sorted level lists of the same side.
+fn diff_levels(
+    side: Side,
+    old: &[Level],
+    new: &[Level],
+    changes: &mut Vec<(Side, Decimal, Decimal)>,
+) {
+    // Orders two prices by their position in the book: closest to the touch first.
+    let book_order = |a: Decimal, b: Decimal| match side {
+        Side::Buy => b.cmp(&a),
+        Side::Sell => a.cmp(&b),
+    };
+
+    let (mut i, mut j) = (0, 0);
+    loop {
+        match (old.get(i), new.get(j)) {
+            (Some(before), Some(after)) => match book_order(before.price, after.price) {
+                core::cmp::Ordering::Less => {
+                    changes.push((side, before.price, Decimal::ZERO));
+                    i += 1;
+                }
+                core::cmp::Ordering::Greater => {
+                    changes.push((side, after.price, after.quantity));
+                    j += 1;
+                }
+                core::cmp::Ordering::Equal => {
+                    if before.quantity != after.quantity {
+                        changes.push((side, after.price, after.quantity));
+                    }
+                    i += 1;
+                    j += 1;
+                }
+            },
+            (Some(before), None) => {
+                changes.push((side, before.price, Decimal::ZERO));
+                i += 1;
+            }
+            (None, Some(after)) => {
+                changes.push((side, after.price, after.quantity));
+                j += 1;
+            }
+            (None, None) => break,
+        }
+    }
+}
+
+/// Rejects a best bid / best ask pair that is locked or crossed.
+fn check_touch(bid: Decimal, ask: Decimal) -> Result<(), OrderBookError> {
+    if bid == ask {
+        return Err(OrderBookError::LockedBook(bid));
+    }
+    if bid > ask {
+        return Err(OrderBookError::CrossedBook(bid, ask));
+    }
+    Ok(())
+}
+
+/// Runs the logical book checks, downgrading issues to warnings where `options` allow.
+fn validate_book_logic(
+    book: &OrderBook,
+    spans: &LevelSpans,
+    options: &ParseOptions,
+    warnings: &mut Vec<OrderBookWarning>,
+) -> Result<(), OrderBookError> {
+    let mut report = |issue: OrderBookError| -> Result<(), OrderBookError> {
+        warnings.push(downgrade_issue(issue, options)?);
+        Ok(())
+    };
+
+    for (i, window) in book.bids.windows(2).enumerate() {
+        let span = spans.bids.get(i + 1).copied();
+        if window[0].price == window[1].price {
+            report(OrderBookError::DuplicatePrice(window[0].price, span))?;
+        } else if window[0].price < window[1].price {
+            report(OrderBookError::BidsUnsorted(window[1].price, span))?;
+        }
+    }
+    for (i, window) in book.asks.windows(2).enumerate() {
+        let span = spans.asks.get(i + 1).copied();
+        if window[0].price == window[1].price {
+            report(OrderBookError::DuplicatePrice(window[0].price, span))?;
+        } else if window[0].price > window[1].price {
+            report(OrderBookError::AsksUnsorted(window[1].price, span))?;
+        }
+    }
+    if let (Some(bid), Some(ask)) = (book.best_bid(false), book.best_ask(false))
+        && let Err(issue) = check_touch(bid.price, ask.price)
+    {
+        report(issue)?;
+    }
+    Ok(())
+}
+
+/// Converts a validation error into a warning if `options` downgrade it,
+/// otherwise hands the error back.
+fn downgrade_issue(
+    issue: OrderBookError,
+    options: &ParseOptions,
+) -> Result<OrderBookWarning, OrderBookError> {
+    match issue {
+        OrderBookError::CrossedBook(bid, ask) if options.crossed_as_warning => {
+            Ok(OrderBookWarning::CrossedBook(bid, ask))
+        }
+        OrderBookError::LockedBook(price) if options.crossed_as_warning => {
+            Ok(OrderBookWarning::LockedBook(price))
+        }
+        OrderBookError::BidsUnsorted(price, _) if options.unsorted_as_warning => {
+            Ok(OrderBookWarning::BidsUnsorted(price))
+        }
+        OrderBookError::AsksUnsorted(price, _) if options.unsorted_as_warning => {
+            Ok(OrderBookWarning::AsksUnsorted(price))
+        }
+        OrderBookError::DuplicatePrice(price, _) if options.unsorted_as_warning => {
+            Ok(OrderBookWarning::DuplicatePrice(price))
+        }
+        other => Err(other),
+    }
+}
+
+fn validate_options(book: &OrderBook, options: &ValidationOptions) -> Result<(), OrderBookError> {
+    if options.forbid_zero_top {
+        for top in [book.bids.first(), book.asks.first()].into_iter().flatten() {
+            if top.quantity.is_zero() {
+                return Err(OrderBookError::ZeroTopQuantity(top.price));
+            }
+        }
+    }
+    if let Some(max_skew) = options.max_depth_skew
+        && book.bids.len().abs_diff(book.asks.len()) > max_skew
+    {
+        return Err(OrderBookError::DepthSkew(book.bids.len(), book.asks.len()));
+    }
+    Ok(())
+}
+
+fn validate_instrument_rules(
+    book: &OrderBook,
+    config: &InstrumentConfig,
+) -> Result<(), OrderBookError> {
+    if config.tick_size <= Decimal::ZERO || config.lot_step <= Decimal::ZERO {
+        return Err(OrderBookError::InvalidConfig(format!(
+            "tick_size ({}) and lot_step ({}) must be positive",
+            config.tick_size, config.lot_step
+        )));
+    }
+
+    let all_levels = book.bids.iter().chain(book.asks.iter());
+    for level in all_levels {
+        if config.min_price.is_some_and(|min| level.price < min)
+            || config.max_price.is_some_and(|max| level.price > max)
+        {
+            return Err(OrderBookError::PriceOutOfRange(level.price));
+        }
         if !(level.price % config.tick_size).is_zero() {
             return Err(OrderBookError::InvalidTickSize(
                 level.price,
                 config.tick_size,
+                config.nearest_tick(level.price),
             ));
         }
         if level.quantity < config.min_lot {
@@ -1,9 +1,12 @@
 use anyhow::{Context, Result, bail};
 use clap::{Parser, Subcommand, ValueEnum};
-use order_book_parser::{InstrumentConfig, Side, parse_order_book};
+#[cfg(feature = "gzip")]
+use order_book_parser::parse_order_book_gz;
+use order_book_parser::{InstrumentConfig, OrderBook, Side, parse_order_book_from_reader};
 use rust_decimal::Decimal;
-use std::fs;
-use std::path::PathBuf;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
 
 #[derive(Parser)]
 #[command(name = "order_book_parser")]
@@ -21,13 +24,21 @@ enum TradeSide {
     Sell,
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
 #[derive(Subcommand)]
 enum Commands {
-    /// Parses a file. Requires full instrument configuration.
+    /// Parses one or more files. Requires full instrument configuration.
     Parse {
-        /// Path to the input file containing order book data.
-        #[arg(short, long)]
-        file: PathBuf,
+        /// Path to the input file containing order book data, or `-` to read stdin.
+        /// Files ending in `.gz` are decompressed (requires the `gzip` feature).
+        /// Repeat the flag or list several paths to parse multiple files.
+        #[arg(short, long, required = true, num_args = 1..)]
+        file: Vec<PathBuf>,
 
         /// Instrument Tick Size (e.g., 0.5). REQUIRED.
         #[arg(long)]
@@ -46,8 +57,46 @@ enum Commands {
         action: Option<TradeSide>,
 
         /// Amount to trade. Must match min_lot and lot_step rules.
-        #[arg(long, requires = "action")]
-        amount: Option<f64>,
+        /// Parsed as an exact decimal, so `0.3` is exactly `0.3`.
+        #[arg(long, requires = "action", value_parser = parse_exact_decimal)]
+        amount: Option<Decimal>,
+
+        /// Only parse and validate: print nothing on success and report
+        /// failures through the exit code.
+        #[arg(long, conflicts_with = "action")]
+        validate_only: bool,
+    },
+    /// Validates every file in a directory and prints a per-file summary.
+    Batch {
+        /// Directory containing order book snapshot files.
+        #[arg(short, long)]
+        dir: PathBuf,
+
+        /// Instrument Tick Size (e.g., 0.5). REQUIRED.
+        #[arg(long)]
+        tick_size: f64,
+
+        /// Instrument Minimum Lot size (e.g., 1.0). REQUIRED.
+        #[arg(long)]
+        min_lot: f64,
+
+        /// Instrument Lot Step (e.g., 1.0). REQUIRED.
+        #[arg(long)]
+        lot_step: f64,
+    },
+    /// Compares two snapshot files and prints the per-level changes.
+    Diff {
+        /// Path to the earlier snapshot.
+        #[arg(long)]
+        old: PathBuf,
+
+        /// Path to the later snapshot.
+        #[arg(long)]
+        new: PathBuf,
+
+        /// Output format for the changes.
+        #[arg(long, value_enum, default_value = "text")]
+        output: OutputFormat,
     },
     /// Displays credits information.
     Credits,
@@ -72,49 +121,221 @@ fn main() -> Result<()> {
             lot_step,
             action,
             amount,
+            validate_only,
         } => {
-            println!("Reading file: {:?}", file);
-            let content = fs::read_to_string(&file)
-                .with_context(|| format!("Could not read file `{:?}`", file))?;
-            let cleaned_content = content.trim();
-
-            println!(
-                "Applying Config: Tick={}, MinLot={}, Step={}",
-                tick_size, min_lot, lot_step
-            );
-            let config = InstrumentConfig::new(tick_size, min_lot, lot_step);
-
-            match parse_order_book(cleaned_content, Some(&config)) {
-                Ok(mut book) => {
-                    println!("\n✅ Successfully parsed and validated Order Book!");
-                    println!("{}", book);
-
-                    if let (Some(trade_side), Some(trade_amount_f64)) = (action, amount) {
-                        let trade_qty =
-                            Decimal::from_f64_retain(trade_amount_f64).unwrap_or_default();
-
-                        validate_order_params(trade_qty, &config)?;
-
-                        let lib_side = match trade_side {
-                            TradeSide::Buy => Side::Buy,
-                            TradeSide::Sell => Side::Sell,
-                        };
-
-                        perform_trade(&mut book, lib_side, trade_qty)?;
-                    }
+            let config = InstrumentConfig::try_new(tick_size, min_lot, lot_step)?;
+            let trade = match (action, amount) {
+                (Some(trade_side), Some(trade_qty)) => {
+                    validate_order_params(trade_qty, &config)?;
+                    let lib_side = match trade_side {
+                        TradeSide::Buy => Side::Buy,
+                        TradeSide::Sell => Side::Sell,
+                    };
+                    Some((lib_side, trade_qty))
+                }
+                _ => None,
+            };
+
+            // A single file keeps the plain output and propagates its error;
+            // with several, each gets a header and failures are tallied.
+            if let [single] = file.as_slice() {
+                return run_parse(single, &config, trade, validate_only);
+            }
+            let mut failed = 0;
+            for path in &file {
+                if !validate_only {
+                    println!(
+                        "==================== {} ====================",
+                        path.display()
+                    );
                 }
-                Err(e) => {
-                    eprintln!("\n Error processing order book:");
-                    eprintln!("   {}", e);
-                    eprintln!("   Hint: Check if your file data complies with the tick/lot rules.");
+                if let Err(e) = run_parse(path, &config, trade, validate_only) {
+                    failed += 1;
+                    eprintln!("ERROR  {}: {:#}", path.display(), e);
                 }
             }
+            if failed > 0 {
+                bail!("{} of {} file(s) failed", failed, file.len());
+            }
+        }
+        Commands::Batch {
+            dir,
+            tick_size,
+            min_lot,
+            lot_step,
+        } => {
+            let config = InstrumentConfig::try_new(tick_size, min_lot, lot_step)?;
+            run_batch(&dir, &config)?;
+        }
+        Commands::Diff { old, new, output } => {
+            run_diff(&old, &new, output)?;
+        }
+    }
+
+    Ok(())
+}
+
+fn run_parse(
+    file: &Path,
+    config: &InstrumentConfig,
+    trade: Option<(Side, Decimal)>,
+    validate_only: bool,
+) -> Result<()> {
+    let reader: Box<dyn Read> = if file == Path::new("-") {
+        Box::new(io::stdin().lock())
+    } else {
+        Box::new(File::open(file).with_context(|| format!("Could not open file `{:?}`", file))?)
+    };
+
+    if validate_only {
+        parse_snapshot(file, reader, Some(config))?;
+        return Ok(());
+    }
+
+    if file == Path::new("-") {
+        println!("Reading from stdin");
+    } else {
+        println!("Reading file: {:?}", file);
+    }
+    println!(
+        "Applying Config: Tick={}, MinLot={}, Step={}",
+        config.tick_size, config.min_lot, config.lot_step
+    );
+
+    match parse_snapshot(file, reader, Some(config)) {
+        Ok(mut book) => {
+            println!("\n✅ Successfully parsed and validated Order Book!");
+            println!("{}", book);
+
+            if let Some((side, qty)) = trade {
+                perform_trade(&mut book, side, qty)?;
+            }
+            Ok(())
+        }
+        Err(e) => Err(e.context(
+            "Error processing order book. \
+             Hint: Check if your file data complies with the tick/lot rules.",
+        )),
+    }
+}
+
+fn run_batch(dir: &Path, config: &InstrumentConfig) -> Result<()> {
+    let mut files = Vec::new();
+    for entry in
+        fs::read_dir(dir).with_context(|| format!("Could not read directory `{:?}`", dir))?
+    {
+        let path = entry?.path();
+        if path.is_file() {
+            files.push(path);
+        }
+    }
+    files.sort();
+
+    let mut failed = 0;
+    for path in &files {
+        let result = File::open(path)
+            .map_err(anyhow::Error::from)
+            .and_then(|file| parse_snapshot(path, file, Some(config)));
+        match result {
+            Ok(_) => println!("OK     {}", path.display()),
+            Err(e) => {
+                failed += 1;
+                println!("ERROR  {}: {}", path.display(), e);
+            }
         }
     }
 
+    println!("\n{} file(s) checked, {} failed", files.len(), failed);
+    if failed > 0 {
+        bail!("{} of {} file(s) failed validation", failed, files.len());
+    }
     Ok(())
 }
 
+fn run_diff(old_path: &Path, new_path: &Path, output: OutputFormat) -> Result<()> {
+    let load = |path: &Path| -> Result<OrderBook> {
+        let file = File::open(path).with_context(|| format!("Could not open file `{:?}`", path))?;
+        parse_snapshot(path, file, None)
+    };
+    let old = load(old_path)?;
+    let new = load(new_path)?;
+
+    let changes: Vec<(&str, Side, Decimal, Decimal, Decimal)> = old
+        .diff(&new)
+        .into_iter()
+        .map(|(side, price, quantity)| {
+            // A level missing from the old book is new, even if it appears
+            // with zero quantity; one that drops to zero has been removed.
+            let before = old.quantity_at(side, price);
+            let kind = match before {
+                None => "added",
+                Some(_) if quantity.is_zero() => "removed",
+                Some(_) => "resized",
+            };
+            (kind, side, price, before.unwrap_or_default(), quantity)
+        })
+        .collect();
+
+    match output {
+        OutputFormat::Text => {
+            for (kind, side, price, before, after) in &changes {
+                let book_side = match side {
+                    Side::Buy => "BID",
+                    Side::Sell => "ASK",
+                };
+                println!(
+                    "{:<8} {} {} : {} -> {}",
+                    kind, book_side, price, before, after
+                );
+            }
+            println!("\n{} level(s) changed", changes.len());
+        }
+        OutputFormat::Json => {
+            // Every field is a keyword or a decimal, so no string escaping is needed.
+            let entries: Vec<String> = changes
+                .iter()
+                .map(|(kind, side, price, before, after)| {
+                    let book_side = match side {
+                        Side::Buy => "bid",
+                        Side::Sell => "ask",
+                    };
+                    format!(
+                        concat!(
+                            r#"{{"change":"{}","side":"{}","price":"{}","#,
+                            r#""old_quantity":"{}","new_quantity":"{}"}}"#,
+                        ),
+                        kind, book_side, price, before, after
+                    )
+                })
+                .collect();
+            println!("[{}]", entries.join(","));
+        }
+    }
+    Ok(())
+}
+
+/// Parses a snapshot, decompressing it first when `path` has a `.gz` extension.
+fn parse_snapshot(
+    path: &Path,
+    reader: impl Read,
+    config: Option<&InstrumentConfig>,
+) -> Result<OrderBook> {
+    if path.extension().is_some_and(|ext| ext == "gz") {
+        #[cfg(feature = "gzip")]
+        return Ok(parse_order_book_gz(reader, config)?);
+        #[cfg(not(feature = "gzip"))]
+        bail!(
+            "{:?} is gzip-compressed; rebuild with `--features gzip`",
+            path
+        );
+    }
+    Ok(parse_order_book_from_reader(reader, config)?)
+}
+
+fn parse_exact_decimal(raw: &str) -> Result<Decimal, String> {
+    Decimal::from_str_exact(raw.trim()).map_err(|e| e.to_string())
+}
+
 fn validate_order_params(qty: Decimal, config: &InstrumentConfig) -> Result<()> {
     if qty < config.min_lot {
         bail!(
@@ -133,27 +354,24 @@ fn validate_order_params(qty: Decimal, config: &InstrumentConfig) -> Result<()>
     Ok(())
 }
 
-fn perform_trade(book: &mut order_book_parser::OrderBook, side: Side, qty: Decimal) -> Result<()> {
-    println!("\n--- Executing {:?} Market Order for {} ---", side, qty);
+fn perform_trade(book: &mut OrderBook, side: Side, qty: Decimal) -> Result<()> {
+    println!("\n--- Executing {} Market Order for {} ---", side, qty);
 
-    match book.execute_market_order(side, qty) {
-        Ok(position) => {
-            println!("Result: Order Filled!");
-            println!("  - Quantity:    {}", position.quantity);
-            println!("  - Open price:  {}", position.entry_price.round_dp(4));
+    let position = book
+        .execute_market_order(side, qty)
+        .with_context(|| format!("{} market order for {} failed", side, qty))?;
 
-            if let Some(pnl) = position.calculate_pnl(book) {
-                println!("  - PnL:    {}", pnl.round_dp(2));
-            } else {
-                println!("  - PnL:    N/A (Insufficient liquidity to calc exit)");
-            }
+    println!("Result: Order Filled!");
+    println!("  - Quantity:    {}", position.quantity);
+    println!("  - Open price:  {}", position.entry_price.round_dp(4));
 
-            println!("\nUpdated Order Book State:");
-            println!("{}", book);
-        }
-        Err(e) => {
-            eprintln!("Trade Failed: {}", e);
-        }
+    if let Some(pnl) = position.calculate_pnl_rounded(book, 2) {
+        println!("  - PnL:    {}", pnl);
+    } else {
+        println!("  - PnL:    N/A (Insufficient liquidity to calc exit)");
     }
+
+    println!("\nUpdated Order Book State:");
+    println!("{}", book);
     Ok(())
 }
@@ -1,5 +1,12 @@
 use anyhow::Result;
-use order_book_parser::{InstrumentConfig, OrderBookParser, Rule, Side, parse_order_book};
+use order_book_parser::{
+    DelimiterProfile, InstrumentConfig, Level, LevelOrder, MatchPolicy, OrderBook, OrderBookError,
+    OrderBookParser, OrderBookWarning, ParseOptions, Portfolio, Position, QuantityFormat, Rule,
+    Side, TopOfBook, Trade, UncrossStrategy, ValidationOptions, parse_many, parse_order_book,
+    parse_order_book_fast, parse_order_book_from_reader, parse_order_book_into,
+    parse_order_book_multi, parse_order_book_with_options, parse_order_book_with_profile,
+    parse_order_book_with_warnings, parse_raw,
+};
 use pest::Parser;
 use rust_decimal::prelude::*;
 
@@ -107,7 +114,7 @@ fn test_invalid_syntax_order_book() {
 
 #[test]
 fn test_crossed_book_error() {
-    let input = "BIDS:105.0,10;ASKS:105.0,20";
+    let input = "BIDS:105.5,10;ASKS:105.0,20";
     let result = parse_order_book(input, None);
 
     assert!(result.is_err());
@@ -115,6 +122,16 @@ fn test_crossed_book_error() {
     assert!(format!("{}", err).contains("Crossed book detected"));
 }
 
+#[test]
+fn test_locked_book_error() {
+    let input = "BIDS:105.0,10;ASKS:105.0,20";
+    let result = parse_order_book(input, None);
+
+    assert!(result.is_err());
+    let err = result.unwrap_err();
+    assert!(format!("{}", err).contains("Locked book detected"));
+}
+
 #[test]
 fn test_unsorted_bids() {
     let input = "BIDS:100.0,1|101.0,1;ASKS:110.0,1";
@@ -208,3 +225,2554 @@ fn test_partial_fill_logic() -> Result<()> {
 
     Ok(())
 }
+
+#[test]
+fn test_level_iterators() -> Result<()> {
+    let input = "BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10";
+    let book = parse_order_book(input, None)?;
+
+    let bid_prices: Vec<String> = book.iter_bids().map(|l| l.price.to_string()).collect();
+    assert_eq!(bid_prices, vec!["100.0", "99.5"]);
+
+    let ask_prices: Vec<String> = book.iter_asks().map(|l| l.price.to_string()).collect();
+    assert_eq!(ask_prices, vec!["101.0", "102.0"]);
+
+    let tagged: Vec<(Side, String)> = book
+        .iter_levels()
+        .map(|(side, l)| (side, l.price.to_string()))
+        .collect();
+    assert_eq!(
+        tagged,
+        vec![
+            (Side::Buy, "100.0".to_string()),
+            (Side::Buy, "99.5".to_string()),
+            (Side::Sell, "101.0".to_string()),
+            (Side::Sell, "102.0".to_string()),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_quantity_at_price() -> Result<()> {
+    let input = "BIDS:100.0,10|99.5,20|99.0,30;ASKS:101.0,5|102.0,10|103.0,15";
+    let book = parse_order_book(input, None)?;
+
+    assert_eq!(
+        book.quantity_at(Side::Buy, Decimal::from_str("99.5")?),
+        Some(Decimal::from(20))
+    );
+    assert_eq!(
+        book.quantity_at(Side::Sell, Decimal::from_str("103")?),
+        Some(Decimal::from(15))
+    );
+
+    assert_eq!(
+        book.quantity_at(Side::Buy, Decimal::from_str("101.0")?),
+        None
+    );
+    assert_eq!(
+        book.quantity_at(Side::Sell, Decimal::from_str("101.5")?),
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_forbid_zero_top_validation() {
+    let input = "BIDS:100.0,0|99.5,20;ASKS:101.0,5";
+    assert!(parse_order_book(input, None).is_ok());
+
+    let options = ParseOptions {
+        validation: ValidationOptions {
+            forbid_zero_top: true,
+            ..ValidationOptions::default()
+        },
+        ..ParseOptions::default()
+    };
+    let result = parse_order_book_with_options(input, None, &options);
+    assert!(result.is_err());
+    assert!(format!("{}", result.unwrap_err()).contains("has zero quantity"));
+
+    let healthy = "BIDS:100.0,10|99.5,0;ASKS:101.0,5";
+    assert!(parse_order_book_with_options(healthy, None, &options).is_ok());
+}
+
+#[test]
+fn test_best_price_skip_zero() -> Result<()> {
+    let input = "BIDS:100.0,0|99.5,20;ASKS:101.0,0|101.5,0|102.0,7";
+    let book = parse_order_book(input, None)?;
+
+    assert_eq!(book.best_bid(false).unwrap().price.to_string(), "100.0");
+    assert_eq!(book.best_bid(true).unwrap().price.to_string(), "99.5");
+
+    assert_eq!(book.best_ask(false).unwrap().price.to_string(), "101.0");
+    assert_eq!(book.best_ask(true).unwrap().price.to_string(), "102.0");
+
+    let empty = parse_order_book("BIDS:5,0;ASKS:", None)?;
+    assert!(empty.best_bid(true).is_none());
+    assert!(empty.best_ask(false).is_none());
+
+    Ok(())
+}
+
+#[test]
+fn test_truncate_depth_keeps_top_levels() -> Result<()> {
+    let input = "BIDS:100.0,10|99.5,20|99.0,30;ASKS:101.0,5|102.0,10|103.0,15";
+    let mut book = parse_order_book(input, None)?;
+
+    let top = book.top_n(2);
+    assert_eq!(top.bids.len(), 2);
+    assert_eq!(top.asks.len(), 2);
+    assert_eq!(top.bids[1].price.to_string(), "99.5");
+    assert_eq!(top.asks[1].price.to_string(), "102.0");
+    assert_eq!(book.bids.len(), 3);
+
+    book.truncate_depth(1);
+    assert_eq!(book.bids.len(), 1);
+    assert_eq!(book.asks.len(), 1);
+    assert_eq!(book.bids[0].price.to_string(), "100.0");
+    assert_eq!(book.asks[0].price.to_string(), "101.0");
+
+    book.truncate_depth(5);
+    assert_eq!(book.bids.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_kyle_lambda_linear_book() -> Result<()> {
+    let input = "BIDS:100.0,20|99.0,20|98.0,20;ASKS:101.0,10|102.0,10|103.0,10|104.0,10";
+    let book = parse_order_book(input, None)?;
+
+    assert_eq!(
+        book.kyle_lambda(Side::Buy, 3),
+        Some(Decimal::from_str("0.1")?)
+    );
+    assert_eq!(
+        book.kyle_lambda(Side::Buy, 10),
+        Some(Decimal::from_str("0.1")?)
+    );
+    assert_eq!(
+        book.kyle_lambda(Side::Sell, 3),
+        Some(Decimal::from_str("0.05")?)
+    );
+
+    assert_eq!(book.kyle_lambda(Side::Buy, 1), None);
+
+    // Deep books of cheap tokens overflow the variance term.
+    let deep = parse_order_book(
+        "BIDS:1,1;ASKS:2,300000000000000|3,300000000000000|4,300000000000000",
+        None,
+    )?;
+    assert_eq!(deep.kyle_lambda(Side::Buy, 3), None);
+    let shallower = parse_order_book(
+        "BIDS:1,1;ASKS:2,100000000000|3,100000000000|4,100000000000",
+        None,
+    )?;
+    assert_eq!(
+        shallower.kyle_lambda(Side::Buy, 3),
+        Some(Decimal::from_str("0.00000000001")?)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_round_to_tick_option() -> Result<()> {
+    let config = InstrumentConfig::new(0.5, 1.0, 1.0);
+    let input = "BIDS:100.3,10.2|99.4,4.6;ASKS:101.26,5";
+
+    let strict = parse_order_book(input, Some(&config));
+    assert!(format!("{}", strict.unwrap_err()).contains("multiple of tick size"));
+
+    let options = ParseOptions {
+        round_to_tick: true,
+        ..ParseOptions::default()
+    };
+    let book = parse_order_book_with_options(input, Some(&config), &options)?;
+
+    assert_eq!(book.bids[0].price, Decimal::from_str("100.5")?);
+    assert_eq!(book.bids[0].quantity, Decimal::from(10));
+    assert_eq!(book.bids[1].price, Decimal::from_str("99.5")?);
+    assert_eq!(book.bids[1].quantity, Decimal::from(5));
+    assert_eq!(book.asks[0].price, Decimal::from_str("101.5")?);
+
+    // Rounding a huge price to a fine tick overflows instead of panicking.
+    let fine = InstrumentConfig::from_str_parts("0.0001", "1", "1")?;
+    let huge = parse_order_book_with_options(
+        "BIDS:79228162514264337593543950,1;ASKS:",
+        Some(&fine),
+        &options,
+    );
+    assert!(matches!(huge, Err(OrderBookError::Overflow)));
+
+    Ok(())
+}
+
+#[test]
+fn test_order_book_and_position_equality() -> Result<()> {
+    let input = "BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10";
+    let book_a = parse_order_book(input, None)?;
+    let mut book_b = parse_order_book(input, None)?;
+    assert_eq!(book_a, book_b);
+
+    let position = book_b.execute_market_order(Side::Buy, Decimal::from(5))?;
+    assert_ne!(book_a, book_b);
+
+    let mut book_c = parse_order_book(input, None)?;
+    assert_eq!(
+        position,
+        book_c.execute_market_order(Side::Buy, Decimal::from(5))?
+    );
+    assert_eq!(book_b, book_c);
+
+    Ok(())
+}
+
+#[test]
+fn test_reference_price() -> Result<()> {
+    let fallback = Decimal::from(42);
+
+    let two_sided = parse_order_book("BIDS:100.0,10;ASKS:101.0,5", None)?;
+    assert_eq!(two_sided.mid_price(), Some(Decimal::from_str("100.5")?));
+    assert_eq!(
+        two_sided.reference_price(fallback),
+        Decimal::from_str("100.5")?
+    );
+
+    let bids_only = parse_order_book("BIDS:100.0,10;ASKS:", None)?;
+    assert_eq!(bids_only.mid_price(), None);
+    assert_eq!(bids_only.reference_price(fallback), Decimal::from(100));
+
+    let asks_only = parse_order_book("BIDS:;ASKS:101.0,5", None)?;
+    assert_eq!(asks_only.reference_price(fallback), Decimal::from(101));
+
+    let empty = OrderBook::default();
+    assert_eq!(empty.reference_price(fallback), fallback);
+
+    Ok(())
+}
+
+#[cfg(feature = "binary")]
+#[test]
+fn test_binary_roundtrip() -> Result<()> {
+    let input = "BIDS:100.25,10|99.5,20;ASKS:101.0,5.125|102.0,10";
+    let book = parse_order_book(input, None)?;
+
+    let bytes = book.to_bytes()?;
+    let decoded = OrderBook::from_bytes(&bytes)?;
+    assert_eq!(book, decoded);
+    assert_eq!(decoded.bids[0].price.to_string(), "100.25");
+
+    let corrupted = OrderBook::from_bytes(&bytes[..bytes.len() / 2]);
+    assert!(format!("{}", corrupted.unwrap_err()).contains("Failed to process binary"));
+
+    Ok(())
+}
+
+#[test]
+fn test_non_tradeable_levels_are_skipped() -> Result<()> {
+    let input = "BIDS:100.0,10|99.0,10;ASKS:101.0,5|102.0,10";
+    let mut book = parse_order_book(input, None)?;
+    assert!(book.asks.iter().all(|l| l.tradeable));
+
+    book.asks[0].tradeable = false;
+    book.bids[0].tradeable = false;
+
+    assert_eq!(book.best_ask(false).unwrap().price.to_string(), "102.0");
+    assert_eq!(book.best_bid(false).unwrap().price.to_string(), "99.0");
+
+    let position = book.execute_market_order(Side::Buy, Decimal::from(3))?;
+    assert_eq!(position.entry_price.to_string(), "102.0");
+    assert_eq!(book.asks[0].quantity, Decimal::from(5));
+    assert_eq!(book.asks[1].quantity, Decimal::from(7));
+
+    let pnl = position.calculate_pnl(&book).unwrap();
+    assert_eq!(pnl, Decimal::from(-9));
+
+    Ok(())
+}
+
+#[test]
+fn test_vwap_top_levels() -> Result<()> {
+    let input = "BIDS:100.0,10|99.0,30|98.0,60;ASKS:101.0,10|102.0,10";
+    let book = parse_order_book(input, None)?;
+
+    assert_eq!(book.vwap_top(Side::Buy, 1), Some(Decimal::from(100)));
+    assert_eq!(
+        book.vwap_top(Side::Buy, 2),
+        Some(Decimal::from_str("99.25")?)
+    );
+    assert_eq!(
+        book.vwap_top(Side::Sell, 10),
+        Some(Decimal::from_str("101.5")?)
+    );
+
+    let empty = parse_order_book("BIDS:;ASKS:101.0,1", None)?;
+    assert_eq!(empty.vwap_top(Side::Buy, 3), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_place_resting_orders() -> Result<()> {
+    let input = "BIDS:100.0,10|99.0,10;ASKS:101.0,5|103.0,10";
+    let mut book = parse_order_book(input, None)?;
+
+    book.place_resting(Side::Buy, Decimal::from_str("99.5")?, Decimal::from(4))?;
+    let bid_prices: Vec<String> = book.bids.iter().map(|l| l.price.to_string()).collect();
+    assert_eq!(bid_prices, vec!["100.0", "99.5", "99.0"]);
+
+    book.place_resting(Side::Sell, Decimal::from(103), Decimal::from(2))?;
+    assert_eq!(book.asks.len(), 2);
+    assert_eq!(book.asks[1].quantity, Decimal::from(12));
+
+    book.place_resting(Side::Sell, Decimal::from(102), Decimal::from(1))?;
+    assert_eq!(book.asks[1].price, Decimal::from(102));
+
+    let locked = book.place_resting(Side::Buy, Decimal::from(101), Decimal::from(1));
+    assert!(format!("{}", locked.unwrap_err()).contains("Locked book detected"));
+    let crossed = book.place_resting(Side::Buy, Decimal::from(102), Decimal::from(1));
+    assert!(format!("{}", crossed.unwrap_err()).contains("Crossed book detected"));
+    let crossed = book.place_resting(Side::Sell, Decimal::from(100), Decimal::from(1));
+    assert!(crossed.is_err());
+
+    let zero = book.place_resting(Side::Buy, Decimal::from(98), Decimal::ZERO);
+    assert!(format!("{}", zero.unwrap_err()).contains("must be positive"));
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_into_matches_parse() -> Result<()> {
+    let mut book = OrderBook::default();
+    let inputs = [
+        "BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10",
+        "BIDS:98.0,1;ASKS:",
+        "BIDS:;ASKS:99.0,3|99.5,4",
+    ];
+
+    for input in inputs {
+        let expected = parse_order_book(input, None)?;
+        parse_order_book_into(input, &mut book, None)?;
+        assert_eq!(book, expected);
+    }
+
+    let bad = parse_order_book_into("BIDS:100.0,1;ASKS:99.0,1", &mut book, None);
+    assert!(format!("{}", bad.unwrap_err()).contains("Crossed book detected"));
+
+    Ok(())
+}
+
+#[test]
+fn test_inferred_tick_per_side() -> Result<()> {
+    let input = "BIDS:100.0,1|99.5,1|98.5,1;ASKS:101.0,1|101.25,1|101.75,1";
+    let book = parse_order_book(input, None)?;
+
+    let (bid_tick, ask_tick) = book.inferred_tick_per_side();
+    assert_eq!(bid_tick, Some(Decimal::from_str("0.5")?));
+    assert_eq!(ask_tick, Some(Decimal::from_str("0.25")?));
+
+    let thin = parse_order_book("BIDS:100.0,1;ASKS:", None)?;
+    assert_eq!(thin.inferred_tick_per_side(), (None, None));
+
+    Ok(())
+}
+
+#[test]
+fn test_instrument_config_try_new() {
+    assert!(InstrumentConfig::try_new(0.5, 1.0, 1.0).is_ok());
+
+    let zero_tick = InstrumentConfig::try_new(0.0, 1.0, 1.0);
+    assert!(format!("{}", zero_tick.unwrap_err()).contains("tick_size"));
+
+    let nan_lot = InstrumentConfig::try_new(0.5, f64::NAN, 1.0);
+    assert!(format!("{}", nan_lot.unwrap_err()).contains("min_lot"));
+
+    assert!(InstrumentConfig::try_new(0.5, 1.0, -1.0).is_err());
+    assert!(InstrumentConfig::try_new(f64::INFINITY, 1.0, 1.0).is_err());
+}
+
+#[test]
+fn test_zero_tick_does_not_panic() {
+    let config = InstrumentConfig::new(f64::NAN, 1.0, 1.0);
+    let result = parse_order_book("BIDS:100.0,1;ASKS:101.0,1", Some(&config));
+    assert!(format!("{}", result.unwrap_err()).contains("Invalid instrument configuration"));
+}
+
+#[test]
+fn test_max_depth_skew_validation() {
+    let options = ParseOptions {
+        validation: ValidationOptions {
+            max_depth_skew: Some(1),
+            ..ValidationOptions::default()
+        },
+        ..ParseOptions::default()
+    };
+
+    let balanced = "BIDS:100.0,1|99.0,1;ASKS:101.0,1";
+    assert!(parse_order_book_with_options(balanced, None, &options).is_ok());
+
+    let skewed = "BIDS:100.0,1|99.0,1|98.0,1;ASKS:101.0,1";
+    assert!(parse_order_book(skewed, None).is_ok());
+    let result = parse_order_book_with_options(skewed, None, &options);
+    assert!(format!("{}", result.unwrap_err()).contains("3 bid levels vs 1 ask levels"));
+}
+
+#[test]
+fn test_position_merge() -> Result<()> {
+    let mut book = parse_order_book("BIDS:99.0,10;ASKS:100.0,10|102.0,30", None)?;
+
+    let first = book.execute_market_order(Side::Buy, Decimal::from(10))?;
+    let second = book.execute_market_order(Side::Buy, Decimal::from(30))?;
+
+    let merged = first.merge(&second)?;
+    assert_eq!(merged.side, Side::Buy);
+    assert_eq!(merged.quantity, Decimal::from(40));
+    assert_eq!(merged.entry_price, Decimal::from_str("101.5")?);
+
+    let mut other_book = parse_order_book("BIDS:99.0,10;ASKS:100.0,10", None)?;
+    let short = other_book.execute_market_order(Side::Sell, Decimal::from(5))?;
+    let mismatch = first.merge(&short);
+    assert!(format!("{}", mismatch.unwrap_err()).contains("Cannot merge"));
+
+    Ok(())
+}
+
+#[test]
+fn test_crossed_book_as_warning() -> Result<()> {
+    let crossed = "BIDS:102.0,10|99.0,5;ASKS:101.0,5";
+    let options = ParseOptions {
+        crossed_as_warning: true,
+        ..ParseOptions::default()
+    };
+
+    let (book, warnings) = parse_order_book_with_warnings(crossed, None, &options)?;
+    assert_eq!(book.bids.len(), 2);
+    assert_eq!(
+        warnings,
+        vec![OrderBookWarning::CrossedBook(
+            Decimal::from(102),
+            Decimal::from(101)
+        )]
+    );
+
+    let locked = "BIDS:101.0,10;ASKS:101.0,5";
+    let (_, warnings) = parse_order_book_with_warnings(locked, None, &options)?;
+    assert_eq!(
+        warnings,
+        vec![OrderBookWarning::LockedBook(Decimal::from(101))]
+    );
+
+    let unsorted = "BIDS:99.0,1|100.0,1;ASKS:101.0,5";
+    assert!(parse_order_book_with_warnings(unsorted, None, &options).is_err());
+
+    let lenient = ParseOptions {
+        crossed_as_warning: true,
+        unsorted_as_warning: true,
+        ..ParseOptions::default()
+    };
+    let (_, warnings) = parse_order_book_with_warnings(unsorted, None, &lenient)?;
+    assert_eq!(
+        warnings,
+        vec![OrderBookWarning::BidsUnsorted(Decimal::from(100))]
+    );
+
+    let (_, warnings) = parse_order_book_with_warnings(
+        "BIDS:100.0,1;ASKS:101.0,1",
+        None,
+        &ParseOptions::default(),
+    )?;
+    assert!(warnings.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_decay_weighted_mid() -> Result<()> {
+    let input = "BIDS:100.0,10|99.0,10|98.0,10;ASKS:101.0,10|102.0,10|103.0,10";
+    let book = parse_order_book(input, None)?;
+
+    // Equal top quantities make the microprice coincide with the mid price.
+    let (bid, ask) = (&book.bids[0], &book.asks[0]);
+    let microprice =
+        (bid.price * ask.quantity + ask.price * bid.quantity) / (bid.quantity + ask.quantity);
+    assert_eq!(book.decay_weighted_mid(Decimal::ZERO), Some(microprice));
+
+    assert_eq!(
+        book.decay_weighted_mid(Decimal::ONE),
+        Some(Decimal::from_str("100.5")?)
+    );
+
+    let half = book.decay_weighted_mid(Decimal::from_str("0.5")?).unwrap();
+    assert_eq!(half, Decimal::from_str("100.5")?);
+
+    let one_sided = parse_order_book("BIDS:100.0,1;ASKS:", None)?;
+    assert_eq!(one_sided.decay_weighted_mid(Decimal::ONE), None);
+    assert_eq!(book.decay_weighted_mid(Decimal::from(-1)), None);
+    assert_eq!(book.decay_weighted_mid(Decimal::from(1000)), None);
+
+    // With unequal touch quantities the two metrics differ: the heavy bid
+    // pulls the microprice towards the ask, while the decay-weighted mid
+    // ignores quantities and stays at the plain mid.
+    let skewed = parse_order_book("BIDS:100,30|99,10;ASKS:101,10|103,10", None)?;
+    assert_eq!(skewed.fair_value(1), Some(Decimal::from_str("100.75")?));
+    assert_eq!(
+        skewed.decay_weighted_mid(Decimal::ZERO),
+        Some(Decimal::from_str("100.5")?)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_depth_and_emptiness() -> Result<()> {
+    let book = parse_order_book("BIDS:100.0,10|99.5,20;ASKS:101.0,5", None)?;
+    assert_eq!(book.bid_depth(), 2);
+    assert_eq!(book.ask_depth(), 1);
+    assert!(!book.is_empty());
+
+    let one_sided = parse_order_book("BIDS:;ASKS:101.0,5", None)?;
+    assert!(!one_sided.is_empty());
+
+    let empty = OrderBook::default();
+    assert_eq!(empty.bid_depth(), 0);
+    assert!(empty.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_fixture_roundtrip() -> Result<()> {
+    let input = "BIDS:100.00,10|99.5,20.250;ASKS:101.0,5";
+    let mut book = parse_order_book(input, None)?;
+    book.asks[0].tradeable = false;
+
+    let fixture = book.to_fixture();
+    assert_eq!(
+        fixture,
+        "bid 100.00 10 true\nbid 99.5 20.250 true\nask 101.0 5 false\n"
+    );
+
+    let restored = OrderBook::from_fixture(&fixture)?;
+    assert_eq!(restored, book);
+    assert_eq!(restored.bids[1].quantity.to_string(), "20.250");
+
+    let bad = OrderBook::from_fixture("bid 100.0 10 true\nmid 1 1 true\n");
+    assert!(format!("{}", bad.unwrap_err()).contains("line 2"));
+
+    Ok(())
+}
+
+#[test]
+fn test_underscore_digit_grouping() -> Result<()> {
+    assert!(OrderBookParser::parse(Rule::integer, "1_000_000").is_ok());
+    assert!(OrderBookParser::parse(Rule::integer, "_1000").is_err());
+
+    let book = parse_order_book("BIDS:100.0,1_000;ASKS:101.0,2_000", None)?;
+    assert_eq!(book.bids[0].quantity, Decimal::from(1000));
+    assert_eq!(book.asks[0].quantity, Decimal::from(2000));
+
+    assert!(parse_order_book("BIDS:100.0,1_000_;ASKS:101.0,1", None).is_err());
+    assert!(parse_order_book("BIDS:100.0,_1000;ASKS:101.0,1", None).is_err());
+    assert!(parse_order_book("BIDS:100.0,1__000;ASKS:101.0,1", None).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_spread_bps() -> Result<()> {
+    let book = parse_order_book("BIDS:99.5,10;ASKS:100.5,5", None)?;
+    assert_eq!(book.spread_bps(), Some(Decimal::from(100)));
+
+    let tight = parse_order_book("BIDS:9999.5,1;ASKS:10000.5,1", None)?;
+    assert_eq!(tight.spread_bps(), Some(Decimal::ONE));
+
+    let one_sided = parse_order_book("BIDS:99.5,10;ASKS:", None)?;
+    assert_eq!(one_sided.spread_bps(), None);
+
+    let zero = Level {
+        price: Decimal::ZERO,
+        quantity: Decimal::ONE,
+        tradeable: true,
+        id: None,
+        timestamp: None,
+    };
+    let zero_mid = OrderBook {
+        bids: vec![zero.clone()],
+        asks: vec![zero],
+        ..Default::default()
+    };
+    assert_eq!(zero_mid.spread_bps(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_portfolio_aggregation() -> Result<()> {
+    let mut book = parse_order_book("BIDS:99.0,20;ASKS:101.0,20", None)?;
+
+    let mut portfolio = Portfolio::new();
+    portfolio.add(book.execute_market_order(Side::Buy, Decimal::from(10))?);
+    portfolio.add(book.execute_market_order(Side::Sell, Decimal::from(4))?);
+
+    assert_eq!(portfolio.net_exposure(), Decimal::from(6));
+
+    // Long 10 @ 101 marked at 99 => -20; short 4 @ 99 marked at 101 => -8.
+    assert_eq!(portfolio.total_pnl(&book)?, Decimal::from(-28));
+
+    let no_bids = parse_order_book("BIDS:;ASKS:101.0,20", None)?;
+    let err = portfolio.total_pnl(&no_bids).unwrap_err();
+    assert!(format!("{}", err).contains("Position #0"));
+
+    let no_asks = parse_order_book("BIDS:99.0,20;ASKS:", None)?;
+    let err = portfolio.total_pnl(&no_asks).unwrap_err();
+    assert!(format!("{}", err).contains("Position #1"));
+
+    Ok(())
+}
+
+#[test]
+fn test_position_merge_in_place() -> Result<()> {
+    let mut book = parse_order_book("BIDS:99.0,10;ASKS:100.0,10|103.0,10", None)?;
+
+    let mut position = book.execute_market_order(Side::Buy, Decimal::from(10))?;
+    let add_on = book.execute_market_order(Side::Buy, Decimal::from(5))?;
+    position.merge_in_place(&add_on)?;
+
+    assert_eq!(position.quantity, Decimal::from(15));
+    assert_eq!(position.entry_price, Decimal::from(101));
+
+    let short = Position {
+        side: Side::Sell,
+        quantity: Decimal::ONE,
+        entry_price: Decimal::from(99),
+    };
+    assert!(position.merge_in_place(&short).is_err());
+    assert_eq!(position.quantity, Decimal::from(15));
+
+    Ok(())
+}
+
+#[test]
+fn test_position_partial_reduce() -> Result<()> {
+    let mut book = parse_order_book("BIDS:102.0,5|101.0,10;ASKS:103.0,20", None)?;
+    let mut position = Position {
+        side: Side::Buy,
+        quantity: Decimal::from(10),
+        entry_price: Decimal::from(100),
+    };
+
+    // Sells 5 @ 102 and 3 @ 101: VWAP 101.625, realized (101.625 - 100) * 8 = 13.
+    let realized = position.reduce(&mut book, Decimal::from(8))?;
+    assert_eq!(realized, Decimal::from(13));
+    assert_eq!(position.quantity, Decimal::from(2));
+    assert_eq!(book.bids.len(), 1);
+    assert_eq!(book.bids[0].quantity, Decimal::from(7));
+
+    let too_much = position.reduce(&mut book, Decimal::from(3));
+    assert!(format!("{}", too_much.unwrap_err()).contains("only 2 is open"));
+
+    let mut short = Position {
+        side: Side::Sell,
+        quantity: Decimal::from(4),
+        entry_price: Decimal::from(105),
+    };
+    assert_eq!(short.reduce(&mut book, Decimal::from(4))?, Decimal::from(8));
+    assert!(short.quantity.is_zero());
+
+    Ok(())
+}
+
+#[test]
+fn test_level_order_ids() -> Result<()> {
+    assert!(OrderBookParser::parse(Rule::level, "100.5,10,42").is_ok());
+
+    let book = parse_order_book("BIDS:100.0,10,7|99.5,20;ASKS:101.0,5,1_001", None)?;
+    assert_eq!(book.bids[0].id, Some(7));
+    assert_eq!(book.bids[1].id, None);
+    assert_eq!(book.asks[0].id, Some(1001));
+
+    let fixture = book.to_fixture();
+    assert!(fixture.starts_with("bid 100.0 10 true id=7\n"));
+    assert_eq!(OrderBook::from_fixture(&fixture)?, book);
+
+    let overflow = parse_order_book("BIDS:100.0,1,99999999999999999999;ASKS:", None);
+    assert!(format!("{}", overflow.unwrap_err()).contains("Invalid order ID"));
+
+    Ok(())
+}
+
+#[test]
+fn test_crc32_checksum() -> Result<()> {
+    // Canonical string is "12345" + "6789", the standard CRC-32 check input.
+    let book = parse_order_book("BIDS:;ASKS:123.45,67.89", None)?;
+    assert_eq!(book.crc32_top(10), 0xCBF4_3926);
+
+    let input = "BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10";
+    let crc = parse_order_book(input, None)?.crc32_top(10);
+
+    let with_crc = format!("{}|CRC={}", input, crc);
+    assert!(parse_order_book(&with_crc, None).is_ok());
+
+    let tampered = format!("BIDS:100.0,11|99.5,20;ASKS:101.0,5|102.0,10|CRC={}", crc);
+    let result = parse_order_book(&tampered, None);
+    assert!(format!("{}", result.unwrap_err()).contains("Checksum mismatch"));
+
+    let too_big = format!("{}|CRC=99999999999", input);
+    let result = parse_order_book(&too_big, None);
+    assert!(format!("{}", result.unwrap_err()).contains("Invalid checksum value"));
+
+    Ok(())
+}
+
+#[test]
+fn test_delimiter_profiles_roundtrip() -> Result<()> {
+    let book = parse_order_book("BIDS:100.0,10,7|99.5,20;ASKS:101.0,5|102.0,10", None)?;
+
+    for profile in [
+        DelimiterProfile::CANONICAL,
+        DelimiterProfile::SPACE_SEPARATED,
+    ] {
+        let text = profile.render(&book);
+        let parsed = parse_order_book_with_profile(&text, &profile, None)?;
+        assert_eq!(parsed, book);
+    }
+
+    let spaced = "BIDS:100.0,10   99.5,20 ; ASKS: 101.0,5\t102.0,10";
+    let parsed = parse_order_book_with_profile(spaced, &DelimiterProfile::SPACE_SEPARATED, None)?;
+    assert_eq!(parsed.bids.len(), 2);
+    assert_eq!(parsed.asks[1].price.to_string(), "102.0");
+
+    let empty_side = parse_order_book_with_profile(
+        "BIDS:;ASKS:101.0,5",
+        &DelimiterProfile::SPACE_SEPARATED,
+        None,
+    )?;
+    assert!(empty_side.bids.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_order_book_from_str() -> Result<()> {
+    let input = "BIDS:100.0,10|99.5,20;ASKS:101.0,5";
+    let book: OrderBook = input.parse()?;
+    assert_eq!(book, parse_order_book(input, None)?);
+
+    let bad = "BIDS:100.0,10;ASKS:99.0,5".parse::<OrderBook>();
+    assert!(format!("{}", bad.unwrap_err()).contains("Crossed book detected"));
+
+    Ok(())
+}
+
+#[test]
+fn test_liquidity_between_prices() -> Result<()> {
+    let input = "BIDS:100.0,10|99.5,20|99.0,30|98.0,40;ASKS:101.0,5|102.0,10|103.0,15";
+    let book = parse_order_book(input, None)?;
+
+    assert_eq!(
+        book.liquidity_between(Side::Buy, Decimal::from(99), Decimal::from(100)),
+        Decimal::from(60)
+    );
+    assert_eq!(
+        book.liquidity_between(Side::Sell, Decimal::from_str("101.5")?, Decimal::from(200)),
+        Decimal::from(25)
+    );
+    assert_eq!(
+        book.liquidity_between(Side::Sell, Decimal::from(102), Decimal::from(102)),
+        Decimal::from(10)
+    );
+    assert_eq!(
+        book.liquidity_between(
+            Side::Buy,
+            Decimal::from_str("98.1")?,
+            Decimal::from_str("98.9")?
+        ),
+        Decimal::ZERO
+    );
+    assert_eq!(
+        book.liquidity_between(Side::Buy, Decimal::from(100), Decimal::from(99)),
+        Decimal::ZERO
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_invert_book() -> Result<()> {
+    let input = "BIDS:2.0,10|1.6,20;ASKS:2.5,5|4.0,8";
+    let book = parse_order_book(input, None)?;
+    let inverse = book.invert();
+
+    let bid_prices: Vec<Decimal> = inverse.iter_bids().map(|l| l.price).collect();
+    let ask_prices: Vec<Decimal> = inverse.iter_asks().map(|l| l.price).collect();
+    assert_eq!(
+        bid_prices,
+        vec![Decimal::from_str("0.4")?, Decimal::from_str("0.25")?]
+    );
+    assert_eq!(
+        ask_prices,
+        vec![Decimal::from_str("0.5")?, Decimal::from_str("0.625")?]
+    );
+    assert_eq!(inverse.bids[0].quantity, Decimal::from(5));
+    assert_eq!(inverse.asks[1].quantity, Decimal::from(20));
+
+    let mut with_zero = book.clone();
+    with_zero.bids.push(Level {
+        price: Decimal::ZERO,
+        quantity: Decimal::ONE,
+        tradeable: true,
+        id: None,
+        timestamp: None,
+    });
+    assert_eq!(with_zero.invert().ask_depth(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_fill_for_notional() -> Result<()> {
+    let input = "BIDS:99.0,10|98.0,10;ASKS:100.0,10|110.0,10";
+    let book = parse_order_book(input, None)?;
+
+    // 1000 takes the whole first ask, 550 buys 5 units at 110.
+    let (avg, qty) = book
+        .fill_for_notional(Side::Buy, Decimal::from(1550))
+        .unwrap();
+    assert_eq!(qty, Decimal::from(15));
+    assert_eq!(avg * qty, Decimal::from(1550));
+
+    let (avg, qty) = book
+        .fill_for_notional(Side::Sell, Decimal::from(495))
+        .unwrap();
+    assert_eq!(avg, Decimal::from(99));
+    assert_eq!(qty, Decimal::from(5));
+
+    // More notional than the side holds fills everything available.
+    let (_, qty) = book
+        .fill_for_notional(Side::Buy, Decimal::from(1_000_000))
+        .unwrap();
+    assert_eq!(qty, Decimal::from(20));
+
+    assert!(book.fill_for_notional(Side::Buy, Decimal::ZERO).is_none());
+    let one_sided = parse_order_book("BIDS:99.0,10;ASKS:", None)?;
+    assert!(
+        one_sided
+            .fill_for_notional(Side::Buy, Decimal::from(100))
+            .is_none()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_internal_error_and_wildcard_match() {
+    let err = OrderBookError::Internal("unexpected state".to_string());
+    assert_eq!(err.to_string(), "Internal error: unexpected state");
+
+    // Downstream matches must carry a wildcard arm since the enum is non-exhaustive.
+    let label = match err {
+        OrderBookError::Internal(_) => "internal",
+        _ => "other",
+    };
+    assert_eq!(label, "internal");
+}
+
+#[test]
+fn test_parse_from_reader() -> Result<()> {
+    let input = "  BIDS:100.0,10;ASKS:101.0,5\r\n";
+    let book = parse_order_book_from_reader(input.as_bytes(), None)?;
+    assert_eq!(book, parse_order_book("BIDS:100.0,10;ASKS:101.0,5", None)?);
+
+    let invalid_utf8: &[u8] = &[0x42, 0xff, 0xfe];
+    assert!(matches!(
+        parse_order_book_from_reader(invalid_utf8, None),
+        Err(OrderBookError::IoError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_bom_and_surrounding_whitespace() -> Result<()> {
+    let expected = parse_order_book("BIDS:100.0,10;ASKS:101.0,5", None)?;
+
+    let with_bom = "\u{FEFF}BIDS:100.0,10;ASKS:101.0,5\r\n";
+    assert_eq!(parse_order_book(with_bom, None)?, expected);
+    assert_eq!(
+        parse_order_book("\n  BIDS:100.0,10;ASKS:101.0,5  \n", None)?,
+        expected
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_approx_eq_with_tolerance() -> Result<()> {
+    let reference = parse_order_book("BIDS:100.00,10;ASKS:101.00,5", None)?;
+    let rebuilt = parse_order_book("BIDS:100.01,10.001;ASKS:100.99,5", None)?;
+    let tol = Decimal::from_str("0.01")?;
+
+    assert!(reference.approx_eq(&rebuilt, tol, tol));
+    assert!(!reference.approx_eq(&rebuilt, Decimal::from_str("0.005")?, tol));
+    assert!(!reference.approx_eq(&rebuilt, tol, Decimal::ZERO));
+
+    let deeper = parse_order_book("BIDS:100.00,10|99.00,1;ASKS:101.00,5", None)?;
+    assert!(!reference.approx_eq(&deeper, tol, tol));
+
+    Ok(())
+}
+
+#[test]
+fn test_match_incoming_trades_and_rests() -> Result<()> {
+    let input = "BIDS:99.0,10;ASKS:100.0,5|101.0,5|102.0,5";
+    let mut book = parse_order_book(input, None)?;
+
+    let trades = book.match_incoming(Side::Buy, Decimal::from(101), Decimal::from(12));
+    assert_eq!(
+        trades,
+        vec![
+            Trade {
+                price: Decimal::from(100),
+                quantity: Decimal::from(5),
+                maker_side: Side::Sell,
+            },
+            Trade {
+                price: Decimal::from(101),
+                quantity: Decimal::from(5),
+                maker_side: Side::Sell,
+            },
+        ]
+    );
+    // The unfilled 2 rest as the new best bid at the limit price.
+    assert_eq!(book.best_bid(false).unwrap().price, Decimal::from(101));
+    assert_eq!(book.best_bid(false).unwrap().quantity, Decimal::from(2));
+    assert_eq!(book.best_ask(false).unwrap().price, Decimal::from(102));
+
+    // A sell that does not reach the bids only rests.
+    let trades = book.match_incoming(Side::Sell, Decimal::from(103), Decimal::from(1));
+    assert!(trades.is_empty());
+    assert_eq!(
+        book.quantity_at(Side::Sell, Decimal::from(103)),
+        Some(Decimal::ONE)
+    );
+
+    // Partially consuming a level leaves the rest of it in place.
+    let trades = book.match_incoming(Side::Sell, Decimal::from(100), Decimal::ONE);
+    assert_eq!(trades.len(), 1);
+    assert_eq!(trades[0].maker_side, Side::Buy);
+    assert_eq!(
+        book.quantity_at(Side::Buy, Decimal::from(101)),
+        Some(Decimal::ONE)
+    );
+
+    assert!(
+        book.match_incoming(Side::Buy, Decimal::from(200), Decimal::ZERO)
+            .is_empty()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_raw_exposes_spans() -> Result<()> {
+    let input = "BIDS:100.0,10;ASKS:101.0,5";
+    let root = parse_raw(input)?.next().unwrap();
+    assert_eq!(root.as_rule(), Rule::order_book);
+
+    let sides: Vec<(Rule, usize, usize)> = root
+        .into_inner()
+        .map(|pair| (pair.as_rule(), pair.as_span().start(), pair.as_span().end()))
+        .collect();
+    assert_eq!(
+        sides,
+        vec![(Rule::bids_side, 0, 13), (Rule::asks_side, 14, 26)]
+    );
+
+    assert!(parse_raw("BIDS=100,1").is_err());
+
+    // The root rule does not anchor to the end of input: unparsed text is
+    // left for `parse_order_book` to report as trailing data.
+    let root = parse_raw("BIDS:abc;ASKS:")?.next().unwrap();
+    assert_eq!(root.as_span().end(), 5);
+
+    Ok(())
+}
+
+#[test]
+fn test_validation_errors_carry_level_spans() {
+    let input = "BIDS:100.0,1|101.0,2;ASKS:102.0,1";
+    match parse_order_book(input, None) {
+        Err(OrderBookError::BidsUnsorted(price, Some((start, end)))) => {
+            assert_eq!(price, Decimal::from(101));
+            assert_eq!(&input[start..end], "101.0,2");
+        }
+        other => panic!("expected BidsUnsorted with span, got {:?}", other),
+    }
+
+    // Offsets refer to the caller's input even when it has leading whitespace.
+    let input = "  BIDS:100.0,1;ASKS:102.0,1|102.0,3";
+    match parse_order_book(input, None) {
+        Err(OrderBookError::DuplicatePrice(_, Some((start, end)))) => {
+            assert_eq!(&input[start..end], "102.0,3");
+        }
+        other => panic!("expected DuplicatePrice with span, got {:?}", other),
+    }
+
+    let input = "BIDS:100.0,1;ASKS:103.0,1|102.0,3";
+    match parse_order_book(input, None) {
+        Err(OrderBookError::AsksUnsorted(_, Some((start, _)))) => assert_eq!(start, 26),
+        other => panic!("expected AsksUnsorted with span, got {:?}", other),
+    }
+}
+
+#[test]
+fn test_fingerprint_is_stable_and_canonical() -> Result<()> {
+    let book = parse_order_book("BIDS:100.0,10;ASKS:101.0,5", None)?;
+    let same_value = parse_order_book("BIDS:100.00,10.0;ASKS:101,5.000", None)?;
+    assert_eq!(book, same_value);
+    assert_eq!(book.fingerprint(), same_value.fingerprint());
+    assert_eq!(book.fingerprint(), book.clone().fingerprint());
+
+    let other_qty = parse_order_book("BIDS:100.0,11;ASKS:101.0,5", None)?;
+    assert_ne!(book.fingerprint(), other_qty.fingerprint());
+
+    // Moving a level across sides must change the hash.
+    let bids_only = parse_order_book("BIDS:101.0,5|100.0,10;ASKS:", None)?;
+    let asks_only = parse_order_book("BIDS:;ASKS:100.0,10|101.0,5", None)?;
+    assert_ne!(bids_only.fingerprint(), asks_only.fingerprint());
+
+    assert_eq!(
+        OrderBook::default().fingerprint(),
+        OrderBook::default().fingerprint()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_depth_within_pct_of_mid() -> Result<()> {
+    let input = "BIDS:99.5,10|99.0,20|98.0,30;ASKS:100.5,5|101.0,15|102.5,25";
+    let book = parse_order_book(input, None)?;
+
+    // Mid is 100, so 1% covers bids >= 99 and asks <= 101.
+    assert_eq!(
+        book.depth_within_pct(Decimal::ONE),
+        Some((Decimal::from(30), Decimal::from(20)))
+    );
+    assert_eq!(
+        book.depth_within_pct(Decimal::ZERO),
+        Some((Decimal::ZERO, Decimal::ZERO))
+    );
+    assert_eq!(
+        book.depth_within_pct(Decimal::from(5)),
+        Some((Decimal::from(60), Decimal::from(45)))
+    );
+    assert_eq!(book.depth_within_pct(Decimal::NEGATIVE_ONE), None);
+
+    let one_sided = parse_order_book("BIDS:99.5,10;ASKS:", None)?;
+    assert_eq!(one_sided.depth_within_pct(Decimal::ONE), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_one_sided_books() -> Result<()> {
+    let asks_only = parse_order_book("ASKS:101.0,5|102.0,7", None)?;
+    assert!(asks_only.bids.is_empty());
+    assert_eq!(asks_only.ask_depth(), 2);
+
+    let bids_only = parse_order_book("BIDS:100.0,10", None)?;
+    assert!(bids_only.asks.is_empty());
+    assert_eq!(bids_only.best_bid(false).unwrap().price, Decimal::from(100));
+
+    let with_crc = format!("ASKS:101.0,5|CRC={}", asks_only.top_n(1).crc32_top(10));
+    assert_eq!(parse_order_book(&with_crc, None)?.ask_depth(), 1);
+
+    // A single side must still cover the whole input.
+    assert!(parse_order_book("ASKS:101.0,5 junk", None).is_err());
+    assert!(parse_order_book("BIDS:100.0,10;", None).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_level_constructors() -> Result<()> {
+    let level = Level::new(Decimal::from(100), Decimal::from(5))?;
+    assert_eq!(
+        level,
+        Level::new_unchecked(Decimal::from(100), Decimal::from(5))
+    );
+    assert!(level.tradeable);
+    assert_eq!(level.id, None);
+
+    assert!(matches!(
+        Level::new(Decimal::ZERO, Decimal::ONE),
+        Err(OrderBookError::NonPositiveValue(v)) if v.is_zero()
+    ));
+    assert!(matches!(
+        Level::new(Decimal::ONE, Decimal::NEGATIVE_ONE),
+        Err(OrderBookError::NonPositiveValue(_))
+    ));
+
+    // The unchecked form accepts anything, e.g. an empty level from a feed.
+    assert!(
+        Level::new_unchecked(Decimal::ONE, Decimal::ZERO)
+            .quantity
+            .is_zero()
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_instrument_config_from_str_parts() -> Result<()> {
+    let config = InstrumentConfig::from_str_parts("0.0001", "1", "0.5")?;
+    assert_eq!(config.tick_size, Decimal::from_str("0.0001")?);
+    assert_eq!(config.min_lot, Decimal::ONE);
+    assert_eq!(config.lot_step, Decimal::from_str("0.5")?);
+
+    // The f64 constructors now agree with the exact form.
+    assert_eq!(
+        InstrumentConfig::new(0.0001, 1.0, 0.5).tick_size,
+        config.tick_size
+    );
+    assert_eq!(
+        InstrumentConfig::try_new(0.1, 1.0, 0.5)?.tick_size,
+        Decimal::from_str("0.1")?
+    );
+
+    let book = parse_order_book("BIDS:1.0003,1;ASKS:1.0004,1", Some(&config))?;
+    assert_eq!(book.bid_depth(), 1);
+
+    assert!(matches!(
+        InstrumentConfig::from_str_parts("0", "1", "1"),
+        Err(OrderBookError::InvalidConfig(_))
+    ));
+    assert!(matches!(
+        InstrumentConfig::from_str_parts("abc", "1", "1"),
+        Err(OrderBookError::DecimalError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_diff_and_apply_update_roundtrip() -> Result<()> {
+    let older = parse_order_book("BIDS:100.0,10|99.0,5|98.0,1;ASKS:101.0,5|102.0,7", None)?;
+    let newer = parse_order_book(
+        "BIDS:100.0,12|98.5,3|98.0,1;ASKS:101.5,2|102.0,7|103.0,4",
+        None,
+    )?;
+
+    let changes = older.diff(&newer);
+    assert_eq!(
+        changes,
+        vec![
+            (Side::Buy, Decimal::from(100), Decimal::from(12)),
+            (Side::Buy, Decimal::from(99), Decimal::ZERO),
+            (Side::Buy, Decimal::from_str("98.5")?, Decimal::from(3)),
+            (Side::Sell, Decimal::from(101), Decimal::ZERO),
+            (Side::Sell, Decimal::from_str("101.5")?, Decimal::from(2)),
+            (Side::Sell, Decimal::from(103), Decimal::from(4)),
+        ]
+    );
+
+    let mut rebuilt = older.clone();
+    for (side, price, quantity) in changes {
+        rebuilt.apply_update(side, price, quantity);
+    }
+    assert_eq!(rebuilt, newer);
+
+    assert!(newer.diff(&newer).is_empty());
+    let mut emptied = newer.clone();
+    for (side, price, quantity) in newer.diff(&OrderBook::default()) {
+        assert!(quantity.is_zero());
+        emptied.apply_update(side, price, quantity);
+    }
+    assert!(emptied.is_empty());
+
+    Ok(())
+}
+
+#[cfg(feature = "gzip")]
+#[test]
+fn test_parse_gzip_snapshot() -> Result<()> {
+    use flate2::{Compression, write::GzEncoder};
+    use order_book_parser::parse_order_book_gz;
+    use std::io::Write;
+
+    let input = "BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10\n";
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(input.as_bytes())?;
+    let compressed = encoder.finish()?;
+
+    let book = parse_order_book_gz(compressed.as_slice(), None)?;
+    assert_eq!(book, parse_order_book(input, None)?);
+
+    let truncated = &compressed[..compressed.len() / 2];
+    assert!(matches!(
+        parse_order_book_gz(truncated, None),
+        Err(OrderBookError::IoError(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_price_impact() -> Result<()> {
+    let input = "BIDS:99.0,10|94.05,10;ASKS:100.0,10|110.0,10";
+    let book = parse_order_book(input, None)?;
+
+    // Buying 20 averages 105 against a touch of 100: 5% impact.
+    assert_eq!(
+        book.price_impact(Side::Buy, Decimal::from(20)),
+        Some(Decimal::from_str("0.05")?)
+    );
+    assert_eq!(
+        book.price_impact(Side::Buy, Decimal::from(5)),
+        Some(Decimal::ZERO)
+    );
+    // Selling 20 averages 96.525 against a touch of 99: 2.5% impact.
+    assert_eq!(
+        book.price_impact(Side::Sell, Decimal::from(20)),
+        Some(Decimal::from_str("0.025")?)
+    );
+
+    // Not enough liquidity for the full size.
+    assert_eq!(book.price_impact(Side::Buy, Decimal::from(21)), None);
+    assert_eq!(book.price_impact(Side::Buy, Decimal::ZERO), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_empty_book_requires_allow_empty() -> Result<()> {
+    let err = parse_order_book("BIDS:;ASKS:", None).unwrap_err();
+    assert!(matches!(err, OrderBookError::MissingSection(ref s) if s == "Empty book"));
+    assert!(parse_order_book("ASKS:", None).is_err());
+
+    let options = ParseOptions {
+        allow_empty: true,
+        ..Default::default()
+    };
+    let empty = parse_order_book_with_options("BIDS:;ASKS:", None, &options)?;
+    assert!(empty.is_empty());
+
+    // A single populated side is not an empty book.
+    assert_eq!(parse_order_book("BIDS:;ASKS:101.0,5", None)?.ask_depth(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_cumulative_depth() -> Result<()> {
+    let book = parse_order_book("BIDS:100.0,10|99.0,5;ASKS:101.0,1|102.0,2|103.0,3", None)?;
+
+    assert_eq!(
+        book.cumulative(Side::Buy),
+        vec![
+            (Decimal::from(100), Decimal::from(10), Decimal::from(10)),
+            (Decimal::from(99), Decimal::from(5), Decimal::from(15)),
+        ]
+    );
+    let asks: Vec<Decimal> = book.cumulative(Side::Sell).iter().map(|c| c.2).collect();
+    assert_eq!(
+        asks,
+        vec![Decimal::from(1), Decimal::from(3), Decimal::from(6)]
+    );
+
+    assert!(OrderBook::default().cumulative(Side::Buy).is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_infer_tick_size_across_sides() -> Result<()> {
+    let book = parse_order_book("BIDS:100.50,1|100.00,1;ASKS:101.25,1|102.00,1", None)?;
+    assert_eq!(book.infer_tick_size(), Some(Decimal::from_str("0.25")?));
+
+    // The spread alone is enough when each side has a single level.
+    let touch_only = parse_order_book("BIDS:99.99,1;ASKS:100.01,1", None)?;
+    assert_eq!(
+        touch_only.infer_tick_size(),
+        Some(Decimal::from_str("0.02")?)
+    );
+
+    let single = parse_order_book("BIDS:100.0,1;ASKS:", None)?;
+    assert_eq!(single.infer_tick_size(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_validate_programmatic_book() -> Result<()> {
+    let mut book = OrderBook {
+        bids: vec![Level::new(Decimal::from(100), Decimal::from(2))?],
+        asks: vec![Level::new(Decimal::from(101), Decimal::from(3))?],
+        ..Default::default()
+    };
+    book.validate()?;
+    book.validate_instrument(&InstrumentConfig::new(0.5, 1.0, 1.0))?;
+    assert!(matches!(
+        book.validate_instrument(&InstrumentConfig::new(0.5, 5.0, 1.0)),
+        Err(OrderBookError::InvalidMinLot(_, _))
+    ));
+
+    book.bids
+        .push(Level::new(Decimal::from(100), Decimal::ONE)?);
+    assert!(matches!(
+        book.validate(),
+        Err(OrderBookError::DuplicatePrice(_, None))
+    ));
+
+    book.bids.truncate(1);
+    book.asks
+        .insert(0, Level::new(Decimal::from(100), Decimal::ONE)?);
+    assert!(matches!(
+        book.validate(),
+        Err(OrderBookError::LockedBook(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_pnl_rounded_and_bps() -> Result<()> {
+    let book = parse_order_book("BIDS:100.0,10;ASKS:101.0,10", None)?;
+    let long = Position {
+        side: Side::Buy,
+        quantity: Decimal::from(3),
+        entry_price: Decimal::from_str("99.333")?,
+    };
+
+    assert_eq!(long.calculate_pnl(&book), Some(Decimal::from_str("2.001")?));
+    assert_eq!(
+        long.calculate_pnl_rounded(&book, 2),
+        Some(Decimal::from_str("2.00")?)
+    );
+
+    let short = Position {
+        side: Side::Sell,
+        quantity: Decimal::from(2),
+        entry_price: Decimal::from(100),
+    };
+    // Buying back at 101 loses 2 on a notional of 200: -100 bps.
+    assert_eq!(short.calculate_pnl_bps(&book), Some(Decimal::from(-100)));
+
+    let empty = OrderBook::default();
+    assert_eq!(long.calculate_pnl_rounded(&empty, 2), None);
+    assert_eq!(long.calculate_pnl_bps(&empty), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_clear_and_with_capacity() -> Result<()> {
+    let book = OrderBook::with_capacity(8, 4);
+    assert!(book.is_empty());
+    assert!(book.bids.capacity() >= 8);
+    assert!(book.asks.capacity() >= 4);
+
+    let mut book = parse_order_book("BIDS:100.0,10|99.0,5;ASKS:101.0,5", None)?;
+    book.symbol = Some("BTC-USD".into());
+    book.currency = Some("USD".into());
+    let capacity = book.bids.capacity();
+    book.clear();
+    assert!(book.is_empty());
+    assert_eq!(book.bids.capacity(), capacity);
+    assert_eq!(book, OrderBook::default());
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_order_book_into_reuses_book() -> Result<()> {
+    let mut book = OrderBook::with_capacity(16, 16);
+    parse_order_book_into("BIDS:100.0,10|99.0,5;ASKS:101.0,5", &mut book, None)?;
+    assert_eq!(book.bid_depth(), 2);
+
+    // Metadata from an earlier use of the book must not leak into the result.
+    book.symbol = Some("BTC-USD".into());
+    book.currency = Some("USD".into());
+    parse_order_book_into("BIDS:98.0,1;ASKS:99.0,2", &mut book, None)?;
+    assert_eq!(book, parse_order_book("BIDS:98.0,1;ASKS:99.0,2", None)?);
+    assert!(book.bids.capacity() >= 16);
+
+    assert!(parse_order_book_into("BIDS:abc;ASKS:", &mut book, None).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_fast_parser_matches_pest_parser() {
+    let inputs = [
+        // Canonical inputs handled by the fast path.
+        "BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10",
+        "BIDS:100,1;ASKS:101,1",
+        "BIDS:100.0,10,7|99.0,1;ASKS:101.0,5,42",
+        "BIDS:100.0,10;ASKS:",
+        "BIDS:;ASKS:101.0,5",
+        "BIDS:;ASKS:",
+        "BIDS:100.0,1|101.0,2;ASKS:102.0,1",
+        "BIDS:100.0,1;ASKS:103.0,1|102.0,3",
+        "BIDS:100.0,1;ASKS:102.0,1|102.0,3",
+        "BIDS:101.0,1;ASKS:101.0,1",
+        "BIDS:102.0,1;ASKS:101.0,1",
+        "BIDS:100.0,1,99999999999999999999999;ASKS:101.0,1",
+        "BIDS:100.0,99999999999999999999999999999999;ASKS:101.0,1",
+        // Deviations that must fall back to the grammar.
+        " BIDS:100.0,10;ASKS:101.0,5",
+        "\u{FEFF}BIDS:100.0,10;ASKS:101.0,5",
+        "BIDS:100.0,1_000;ASKS:101.0,5",
+        "BIDS:100.0,10;ASKS:101.0,5|CRC=1",
+        "ASKS:101.0,5",
+        "BIDS:|100.0,10;ASKS:101.0,5",
+        "BIDS:100.0,10;ASKS:101.0,5xyz",
+        "BIDS:100.0,;ASKS:101.0,5",
+        "BIDS:100.0,10 ASKS:101.0,5",
+        "BIDS:.5,1;ASKS:1,1",
+        "garbage",
+        "",
+    ];
+
+    for input in inputs {
+        assert_eq!(
+            format!("{:?}", parse_order_book_fast(input)),
+            format!("{:?}", parse_order_book(input, None)),
+            "mismatch for {:?}",
+            input
+        );
+    }
+}
+
+#[test]
+fn test_effective_spread() -> Result<()> {
+    let book = parse_order_book("BIDS:99.0,10|98.0,10;ASKS:101.0,10|103.0,10", None)?;
+
+    // Mid is 100; buying 20 averages 102, so the effective spread is 4.
+    assert_eq!(
+        book.effective_spread(Side::Buy, Decimal::from(20)),
+        Some(Decimal::from(4))
+    );
+    // At the touch it equals the quoted spread.
+    assert_eq!(
+        book.effective_spread(Side::Sell, Decimal::from(5)),
+        Some(Decimal::TWO)
+    );
+    // Oversized orders use the VWAP of what is available.
+    assert_eq!(
+        book.effective_spread(Side::Buy, Decimal::from(100)),
+        Some(Decimal::from(4))
+    );
+
+    assert_eq!(book.effective_spread(Side::Buy, Decimal::ZERO), None);
+    let one_sided = parse_order_book("BIDS:99.0,10;ASKS:", None)?;
+    assert_eq!(one_sided.effective_spread(Side::Sell, Decimal::ONE), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_integer_tick_scaling() -> Result<()> {
+    let config = InstrumentConfig {
+        price_scale: Some(Decimal::from_str("0.01")?),
+        qty_scale: Some(Decimal::from_str("0.1")?),
+        ..InstrumentConfig::from_str_parts("0.01", "0.1", "0.1")?
+    };
+    let book = parse_order_book("BIDS:10050,25|10049,10;ASKS:10051,3", Some(&config))?;
+
+    assert_eq!(book.bids[0].price, Decimal::from_str("100.50")?);
+    assert_eq!(book.bids[0].quantity, Decimal::from_str("2.5")?);
+    assert_eq!(book.asks[0].price, Decimal::from_str("100.51")?);
+
+    // Validation sees the scaled values.
+    let coarse = InstrumentConfig {
+        tick_size: Decimal::from_str("0.05")?,
+        ..config.clone()
+    };
+    assert!(matches!(
+        parse_order_book("BIDS:10050,25;ASKS:10051,3", Some(&coarse)),
+        Err(OrderBookError::InvalidTickSize(_, _, _))
+    ));
+
+    // Without scales the values are used as written.
+    let unscaled = InstrumentConfig::new(1.0, 1.0, 1.0);
+    let book = parse_order_book("BIDS:10050,25;ASKS:10051,3", Some(&unscaled))?;
+    assert_eq!(book.bids[0].price, Decimal::from(10050));
+
+    // A scale that pushes a value past `Decimal::MAX` is reported, not a panic.
+    let huge = InstrumentConfig {
+        price_scale: Some(Decimal::from_str("10000000000000000000")?),
+        ..unscaled.clone()
+    };
+    assert!(matches!(
+        parse_order_book("BIDS:10000000000,1;ASKS:", Some(&huge)),
+        Err(OrderBookError::Overflow)
+    ));
+    let huge_qty = InstrumentConfig {
+        qty_scale: Some(Decimal::MAX),
+        ..unscaled
+    };
+    assert!(matches!(
+        parse_order_book("BIDS:1,2;ASKS:", Some(&huge_qty)),
+        Err(OrderBookError::Overflow)
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_uncross_strategies() -> Result<()> {
+    let options = ParseOptions {
+        crossed_as_warning: true,
+        ..Default::default()
+    };
+    let input = "BIDS:103.0,1|102.0,5|100.0,2;ASKS:101.0,4|102.0,1|104.0,3";
+    let (crossed, _) = parse_order_book_with_warnings(input, None, &options)?;
+
+    let mut book = crossed.clone();
+    assert_eq!(book.uncross(), 3);
+    assert_eq!(book.best_bid(false).unwrap().price, Decimal::from(102));
+    assert_eq!(book.best_ask(false).unwrap().price, Decimal::from(104));
+    book.validate()?;
+
+    let mut book = crossed.clone();
+    assert_eq!(book.uncross_with(UncrossStrategy::DropBids), 2);
+    assert_eq!(book.best_bid(false).unwrap().price, Decimal::from(100));
+    assert_eq!(book.ask_depth(), 3);
+
+    let mut book = crossed.clone();
+    assert_eq!(book.uncross_with(UncrossStrategy::DropAsks), 2);
+    assert_eq!(book.best_ask(false).unwrap().price, Decimal::from(104));
+    assert_eq!(book.bid_depth(), 3);
+
+    // Already valid books are left alone.
+    let mut clean = parse_order_book("BIDS:100.0,1;ASKS:101.0,1", None)?;
+    assert_eq!(clean.uncross(), 0);
+
+    Ok(())
+}
+
+#[test]
+fn test_strip_comment_lines() -> Result<()> {
+    let input = include_str!("data/commented_snapshot.txt");
+    assert!(parse_order_book(input, None).is_err());
+
+    let options = ParseOptions {
+        strip_comments: true,
+        ..Default::default()
+    };
+    let book = parse_order_book_with_options(input, None, &options)?;
+    assert_eq!(
+        book,
+        parse_order_book("BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10", None)?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_many_collects_every_result() {
+    let inputs = [
+        "BIDS:100.0,10;ASKS:101.0,5",
+        "BIDS:101.0,1;ASKS:100.0,1",
+        "garbage",
+        "BIDS:99.0,1;ASKS:100.0,1",
+    ];
+    let results = parse_many(&inputs, None);
+
+    assert_eq!(results.len(), 4);
+    let ok: Vec<bool> = results.iter().map(|r| r.is_ok()).collect();
+    assert_eq!(ok, vec![true, false, false, true]);
+    assert!(matches!(results[1], Err(OrderBookError::CrossedBook(_, _))));
+    assert!(matches!(results[2], Err(OrderBookError::ParseError(_))));
+}
+
+#[test]
+fn test_side_opposite_and_display() {
+    assert_eq!(Side::Buy.opposite(), Side::Sell);
+    assert_eq!(Side::Sell.opposite(), Side::Buy);
+    assert_eq!(Side::Buy.opposite().opposite(), Side::Buy);
+    assert_eq!(Side::Buy.to_string(), "Buy");
+    assert_eq!(format!("{}", Side::Sell), "Sell");
+}
+
+#[test]
+fn test_book_symbol_and_currency_metadata() -> Result<()> {
+    let mut book = parse_order_book("BIDS:100.0,10;ASKS:101.0,5", None)?;
+    assert_eq!(book.symbol, None);
+    assert_eq!(book.currency, None);
+
+    book.symbol = Some("BTC-USD".to_string());
+    book.currency = Some("USD".to_string());
+    let fixture = book.to_fixture();
+    assert!(fixture.starts_with("symbol \"BTC-USD\"\ncurrency \"USD\"\n"));
+    assert_eq!(OrderBook::from_fixture(&fixture)?, book);
+
+    // Values are escaped, so awkward ones survive the round trip.
+    for symbol in ["", " BTC ", "BTC\nbid 1 1 true", "say \"hi\" \\ \u{7f}"] {
+        book.symbol = Some(symbol.to_string());
+        let restored = OrderBook::from_fixture(&book.to_fixture())?;
+        assert_eq!(restored, book);
+        assert_eq!(restored.bid_depth(), 1);
+    }
+
+    // Metadata takes part in equality but not in the level fingerprint.
+    let plain = parse_order_book("BIDS:100.0,10;ASKS:101.0,5", None)?;
+    assert_ne!(plain, book);
+    assert_eq!(plain.fingerprint(), book.fingerprint());
+
+    assert!(OrderBook::from_fixture("symbol\n").is_err());
+    assert!(OrderBook::from_fixture("symbol BTC\n").is_err());
+    assert!(OrderBook::from_fixture("symbol \"BTC\\q\"\n").is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_quantity_to_price() -> Result<()> {
+    let mut book = parse_order_book(
+        "BIDS:100.0,10|99.0,5|98.0,1;ASKS:101.0,5|102.0,7|103.0,9",
+        None,
+    )?;
+
+    assert_eq!(
+        book.quantity_to_price(Side::Buy, Decimal::from(102)),
+        Decimal::from(12)
+    );
+    assert_eq!(
+        book.quantity_to_price(Side::Sell, Decimal::from_str("98.5")?),
+        Decimal::from(15)
+    );
+    assert_eq!(
+        book.quantity_to_price(Side::Buy, Decimal::from(100)),
+        Decimal::ZERO
+    );
+    assert_eq!(
+        book.quantity_to_price(Side::Buy, Decimal::from(500)),
+        Decimal::from(21)
+    );
+
+    book.asks[1].tradeable = false;
+    assert_eq!(
+        book.quantity_to_price(Side::Buy, Decimal::from(103)),
+        Decimal::from(14)
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_aggregate_duplicate_prices() -> Result<()> {
+    let input = "BIDS:100.0,1|100.0,2|99.0,5;ASKS:101.0,1,7|101.0,4,8|102.0,1";
+    assert!(matches!(
+        parse_order_book(input, None),
+        Err(OrderBookError::DuplicatePrice(_, _))
+    ));
+
+    let options = ParseOptions {
+        aggregate_duplicates: true,
+        ..Default::default()
+    };
+    let book = parse_order_book_with_options(input, None, &options)?;
+    assert_eq!(
+        book.cumulative(Side::Buy),
+        vec![
+            (Decimal::from(100), Decimal::from(3), Decimal::from(3)),
+            (Decimal::from(99), Decimal::from(5), Decimal::from(8)),
+        ]
+    );
+    assert_eq!(book.asks[0].quantity, Decimal::from(5));
+    assert_eq!(book.asks[0].id, None);
+    assert_eq!(book.ask_depth(), 2);
+
+    // Merging two maximal quantities overflows instead of panicking.
+    let huge = "BIDS:1,79228162514264337593543950335|1,79228162514264337593543950335;ASKS:";
+    assert!(matches!(
+        parse_order_book_with_options(huge, None, &options),
+        Err(OrderBookError::Overflow)
+    ));
+
+    // Spans still point at the right level after merging.
+    let unsorted = "BIDS:100.0,1|100.0,2|101.0,5;ASKS:102.0,1";
+    match parse_order_book_with_options(unsorted, None, &options) {
+        Err(OrderBookError::BidsUnsorted(_, Some((start, end)))) => {
+            assert_eq!(&unsorted[start..end], "101.0,5");
+        }
+        other => panic!("expected BidsUnsorted with span, got {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_write_jsonl() -> Result<()> {
+    use order_book_parser::write_jsonl;
+
+    let books = vec![
+        parse_order_book("BIDS:100.0,10;ASKS:101.0,5", None)?,
+        parse_order_book("BIDS:99.5,1;ASKS:100.5,2", None)?,
+    ];
+    let mut out = Vec::new();
+    write_jsonl(books.clone().into_iter(), &mut out)?;
+
+    let text = String::from_utf8(out)?;
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 2);
+    assert!(text.ends_with('\n'));
+    let first: OrderBook = serde_json::from_str(lines[0])?;
+    assert_eq!(first, books[0]);
+    assert!(lines[1].contains("\"99.5\""));
+
+    Ok(())
+}
+
+#[test]
+fn test_fair_value_depth_weighted() -> Result<()> {
+    let book = parse_order_book("BIDS:100.0,30|99.0,10;ASKS:101.0,10|102.0,10", None)?;
+
+    // Top of book: (100 * 10 + 101 * 30) / 40 = 100.75, the microprice.
+    assert_eq!(book.fair_value(1), Some(Decimal::from_str("100.75")?));
+
+    // Two levels: bids VWAP 99.75 on 40, asks VWAP 101.5 on 20.
+    // (99.75 * 20 + 101.5 * 40) / 60 = 100.916...
+    let two = book.fair_value(2).unwrap();
+    assert_eq!(two.round_dp(4), Decimal::from_str("100.9167")?);
+    assert_eq!(book.fair_value(50), book.fair_value(2));
+
+    assert_eq!(book.fair_value(0), None);
+    let one_sided = parse_order_book("BIDS:100.0,30;ASKS:", None)?;
+    assert_eq!(one_sided.fair_value(1), None);
+
+    Ok(())
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_parse_exchange_json_snapshot() -> Result<()> {
+    use order_book_parser::parse_order_book_json;
+
+    let json = r#"{
+        "lastUpdateId": 1027024,
+        "bids": [["100.0", "10"], ["99.5", "20", "0", "4"]],
+        "asks": [["101.0", "5"], [102.0, 10]]
+    }"#;
+    let book = parse_order_book_json(json, Some(&InstrumentConfig::new(0.5, 1.0, 1.0)))?;
+    assert_eq!(
+        book,
+        parse_order_book("BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10", None)?
+    );
+
+    let crossed = r#"{"bids":[["102.0","1"]],"asks":[["101.0","1"]]}"#;
+    assert!(matches!(
+        parse_order_book_json(crossed, None),
+        Err(OrderBookError::CrossedBook(_, _))
+    ));
+    assert!(matches!(
+        parse_order_book_json(r#"{"bids":[["100.0"]],"asks":[]}"#, None),
+        Err(OrderBookError::JsonError(_))
+    ));
+    assert!(matches!(
+        parse_order_book_json("not json", None),
+        Err(OrderBookError::JsonError(_))
+    ));
+
+    assert!(matches!(
+        parse_order_book_json(r#"{"bids":[["-5","-3"]],"asks":[]}"#, None),
+        Err(OrderBookError::NonPositiveValue(_))
+    ));
+    assert!(matches!(
+        parse_order_book_json(r#"{"bids":[["5","-3"]],"asks":[]}"#, None),
+        Err(OrderBookError::NegativeQuantity(_))
+    ));
+    assert!(matches!(
+        parse_order_book_json(r#"{"bids":[["5","1x"]],"asks":[]}"#, None),
+        Err(OrderBookError::JsonError(_))
+    ));
+
+    // Exponent form, both as a string and as a JSON number.
+    let scientific = parse_order_book_json(r#"{"bids":[["1e2", 1e-7]],"asks":[]}"#, None)?;
+    assert_eq!(scientific.bids[0].price, Decimal::from(100));
+    assert_eq!(scientific.bids[0].quantity, Decimal::from_str("0.0000001")?);
+
+    Ok(())
+}
+
+#[test]
+fn test_pnl_with_tick_snapped_exit() -> Result<()> {
+    let book = parse_order_book("BIDS:100.26,10;ASKS:100.74,10", None)?;
+    let config = InstrumentConfig::from_str_parts("0.5", "1", "1")?;
+
+    let long = Position {
+        side: Side::Buy,
+        quantity: Decimal::from(2),
+        entry_price: Decimal::from(100),
+    };
+    assert_eq!(long.calculate_pnl(&book), Some(Decimal::from_str("0.52")?));
+    assert_eq!(
+        long.calculate_pnl_with_config(&book, &config),
+        Some(Decimal::from_str("1.0")?)
+    );
+
+    let short = Position {
+        side: Side::Sell,
+        quantity: Decimal::ONE,
+        entry_price: Decimal::from(101),
+    };
+    // 100.74 snaps to 100.5.
+    assert_eq!(
+        short.calculate_pnl_with_config(&book, &config),
+        Some(Decimal::from_str("0.5")?)
+    );
+
+    assert_eq!(
+        long.calculate_pnl_with_config(&OrderBook::default(), &config),
+        None
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_replay_market_orders() -> Result<()> {
+    let mut book = parse_order_book("BIDS:100.0,10;ASKS:101.0,5|102.0,5", None)?;
+    let results = book.replay(&[
+        (Side::Buy, Decimal::from(4)),
+        (Side::Buy, Decimal::from(4)),
+        (Side::Buy, Decimal::ZERO),
+        (Side::Sell, Decimal::from(3)),
+    ]);
+
+    assert_eq!(results.len(), 4);
+    assert_eq!(results[0].as_ref().unwrap().entry_price, Decimal::from(101));
+    // The second buy sees only 1 left at 101 and walks to 102.
+    assert_eq!(
+        results[1].as_ref().unwrap().entry_price,
+        Decimal::from_str("101.75")?
+    );
+    assert!(matches!(
+        results[2],
+        Err(OrderBookError::NotEnoughLiquidity(_, _))
+    ));
+    assert_eq!(results[3].as_ref().unwrap().quantity, Decimal::from(3));
+
+    assert_eq!(
+        book.quantity_at(Side::Sell, Decimal::from(102)),
+        Some(Decimal::from(2))
+    );
+    assert_eq!(
+        book.quantity_at(Side::Buy, Decimal::from(100)),
+        Some(Decimal::from(7))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_cancel_by_order_id() -> Result<()> {
+    let mut book = parse_order_book("BIDS:100.0,10,1|99.0,5,2;ASKS:101.0,5,3|102.0,7", None)?;
+
+    let cancelled = book.cancel(3).unwrap();
+    assert_eq!(cancelled.price, Decimal::from(101));
+    assert_eq!(cancelled.id, Some(3));
+    assert_eq!(book.best_ask(false).unwrap().price, Decimal::from(102));
+
+    assert_eq!(book.cancel(2).unwrap().quantity, Decimal::from(5));
+    assert_eq!(book.bid_depth(), 1);
+
+    assert_eq!(book.cancel(2), None);
+    assert_eq!(book.cancel(42), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_weighted_age_of_timestamped_levels() -> Result<()> {
+    let mut book = parse_order_book("BIDS:100.0,10|99.0,5;ASKS:101.0,5", None)?;
+    assert_eq!(book.weighted_age(1_000), None);
+
+    book.bids[0].timestamp = Some(900);
+    book.asks[0].timestamp = Some(400);
+    // (10 * 100 + 5 * 600) / 15; the untimestamped bid is ignored.
+    assert_eq!(
+        book.weighted_age(1_000).unwrap().round_dp(4),
+        Decimal::from_str("266.6667")?
+    );
+
+    let fixture = book.to_fixture();
+    assert!(fixture.contains("ts=900"));
+    assert_eq!(OrderBook::from_fixture(&fixture)?, book);
+
+    // A huge quantity aged by a millisecond epoch overflows the weighted sum.
+    book.bids[0].quantity = Decimal::from(100_000_000_000_000_000_u64);
+    book.bids[0].timestamp = Some(0);
+    assert_eq!(book.weighted_age(1_700_000_000_000), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_price_range_bounds() -> Result<()> {
+    let config = InstrumentConfig {
+        min_price: Some(Decimal::from(90)),
+        max_price: Some(Decimal::from(110)),
+        ..InstrumentConfig::new(0.5, 1.0, 1.0)
+    };
+    let book = parse_order_book("BIDS:90.0,10;ASKS:110.0,5", Some(&config))?;
+    assert_eq!(book.bids[0].price, Decimal::from(90));
+
+    assert!(matches!(
+        parse_order_book("BIDS:100.0,10;ASKS:1000000,5", Some(&config)),
+        Err(OrderBookError::PriceOutOfRange(p)) if p == Decimal::from(1_000_000)
+    ));
+    assert!(matches!(
+        parse_order_book("BIDS:89.5,10;ASKS:101.0,5", Some(&config)),
+        Err(OrderBookError::PriceOutOfRange(_))
+    ));
+
+    // Only the configured bound is enforced.
+    let floor_only = InstrumentConfig {
+        max_price: None,
+        ..config
+    };
+    assert!(parse_order_book("BIDS:100.0,10;ASKS:1000000,5", Some(&floor_only)).is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_nearest_tick_suggestion() -> Result<()> {
+    let config = InstrumentConfig::from_str_parts("0.25", "1", "1")?;
+    assert_eq!(
+        config.nearest_tick(Decimal::from_str("100.6")?),
+        Some(Decimal::from_str("100.50")?)
+    );
+    assert_eq!(
+        config.nearest_tick(Decimal::from_str("100.125")?),
+        Some(Decimal::from_str("100.25")?)
+    );
+    assert_eq!(
+        config.nearest_tick(Decimal::from(100)),
+        Some(Decimal::from(100))
+    );
+
+    let err = parse_order_book("BIDS:100.6,10;ASKS:101.0,5", Some(&config)).unwrap_err();
+    let message = err.to_string();
+    assert!(message.contains("multiple of tick size"));
+    assert!(message.contains("did you mean 100.50?"));
+
+    // A suggestion that would overflow is left out of the message.
+    let fine = InstrumentConfig::from_str_parts("0.0001", "1", "1")?;
+    assert_eq!(fine.nearest_tick(Decimal::MAX), None);
+    let coarse = InstrumentConfig::from_str_parts("0.07", "1", "1")?;
+    let err =
+        parse_order_book("BIDS:7922816251426433759354395033,1;ASKS:", Some(&coarse)).unwrap_err();
+    assert!(matches!(err, OrderBookError::InvalidTickSize(_, _, None)));
+    assert_eq!(
+        err.to_string(),
+        "Price 7922816251426433759354395033 is not a multiple of tick size 0.07"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_quantity_first_level_order() -> Result<()> {
+    let options = ParseOptions {
+        level_order: LevelOrder::QuantityFirst,
+        ..ParseOptions::default()
+    };
+    let config = InstrumentConfig::new(0.5, 1.0, 1.0);
+
+    let canonical = parse_order_book(
+        "BIDS:100.0,10|99.5,20,7;ASKS:101.0,5|102.0,10",
+        Some(&config),
+    )?;
+    let swapped = parse_order_book_with_options(
+        "BIDS:10,100.0|20,99.5,7;ASKS:5,101.0|10,102.0",
+        Some(&config),
+        &options,
+    )?;
+    assert_eq!(swapped, canonical);
+    assert_eq!(swapped.bids[1].id, Some(7));
+
+    // The swapped fields are what gets validated: a bid at 200 crosses the ask at 101.
+    assert!(matches!(
+        parse_order_book_with_options("BIDS:100.0,200;ASKS:5,101.0", None, &options),
+        Err(OrderBookError::CrossedBook(_, _))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_notional_overflow_is_reported() -> Result<()> {
+    let mut book = OrderBook {
+        bids: vec![Level::new_unchecked(Decimal::MAX, Decimal::from(2))],
+        asks: vec![
+            Level::new_unchecked(Decimal::MAX, Decimal::ONE),
+            Level::new_unchecked(Decimal::MAX, Decimal::ONE),
+        ],
+        ..OrderBook::default()
+    };
+    let before = book.clone();
+
+    assert!(matches!(
+        book.execute_market_order(Side::Buy, Decimal::from(2)),
+        Err(OrderBookError::Overflow)
+    ));
+    assert_eq!(book, before);
+
+    assert_eq!(book.vwap_top(Side::Buy, 1), None);
+    assert_eq!(book.fair_value(1), None);
+    assert_eq!(book.price_impact(Side::Buy, Decimal::from(2)), None);
+
+    // A single unit still fits and fills normally.
+    let position = book.execute_market_order(Side::Buy, Decimal::ONE)?;
+    assert_eq!(position.entry_price, Decimal::MAX);
+    assert_eq!(book.asks.len(), 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_level_at() -> Result<()> {
+    let book = parse_order_book("BIDS:100.0,10|99.5,20|99.0,5;ASKS:101.0,5", None)?;
+
+    assert_eq!(book.level_at(Side::Buy, 0), book.bids.first());
+    assert_eq!(
+        book.level_at(Side::Buy, 2).map(|level| level.price),
+        Some(Decimal::from(99))
+    );
+    assert_eq!(
+        book.level_at(Side::Sell, 0).map(|level| level.quantity),
+        Some(Decimal::from(5))
+    );
+    assert_eq!(book.level_at(Side::Buy, 3), None);
+    assert_eq!(book.level_at(Side::Sell, 1), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_liquidity_and_error_reports_available() -> Result<()> {
+    let mut book = parse_order_book("BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10", None)?;
+    book.asks[1].tradeable = false;
+
+    assert_eq!(book.liquidity(Side::Buy), Decimal::from(5));
+    assert_eq!(book.liquidity(Side::Sell), Decimal::from(30));
+
+    let err = book
+        .execute_market_order(Side::Sell, Decimal::ZERO)
+        .unwrap_err();
+    assert!(matches!(
+        err,
+        OrderBookError::NotEnoughLiquidity(requested, available)
+            if requested.is_zero() && available == Decimal::from(30)
+    ));
+
+    // An order of exactly `liquidity` empties the tradeable part of the side.
+    let position = book.execute_market_order(Side::Buy, book.liquidity(Side::Buy))?;
+    assert_eq!(position.quantity, Decimal::from(5));
+    assert_eq!(book.liquidity(Side::Buy), Decimal::ZERO);
+
+    Ok(())
+}
+
+#[test]
+fn test_strict_market_order_reports_available() -> Result<()> {
+    let mut book = parse_order_book("BIDS:100.0,10;ASKS:101.0,5|102.0,10", None)?;
+    let before = book.clone();
+
+    assert!(matches!(
+        book.execute_market_order_strict(Side::Buy, Decimal::from(20)),
+        Err(OrderBookError::NotEnoughLiquidity(requested, available))
+            if requested == Decimal::from(20) && available == Decimal::from(15)
+    ));
+    assert_eq!(book, before);
+
+    let position = book.execute_market_order_strict(Side::Buy, Decimal::from(15))?;
+    assert_eq!(position.quantity, Decimal::from(15));
+    assert!(book.asks.is_empty());
+
+    // The IOC path still fills what it can.
+    let partial = book.execute_market_order(Side::Sell, Decimal::from(12))?;
+    assert_eq!(partial.quantity, Decimal::from(10));
+
+    Ok(())
+}
+
+#[test]
+fn test_match_policy_on_aggregated_levels() -> Result<()> {
+    assert_eq!(MatchPolicy::default(), MatchPolicy::Fifo);
+
+    let book = parse_order_book("BIDS:100.0,10;ASKS:101.0,5|102.0,10", None)?;
+    let mut fifo = book.clone();
+    let mut pro_rata = book.clone();
+
+    let a =
+        fifo.execute_market_order_with_policy(Side::Buy, Decimal::from(8), MatchPolicy::Fifo)?;
+    let b = pro_rata.execute_market_order_with_policy(
+        Side::Buy,
+        Decimal::from(8),
+        MatchPolicy::ProRata,
+    );
+    assert!(matches!(b, Err(OrderBookError::Unsupported(_))));
+    assert_eq!(pro_rata, book);
+
+    let mut default = book.clone();
+    assert_eq!(
+        default.execute_market_order(Side::Buy, Decimal::from(8))?,
+        a
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_concentration() -> Result<()> {
+    let book = parse_order_book("BIDS:100.0,10|99.5,10|99.0,10|98.5,10;ASKS:101.0,7", None)?;
+
+    assert_eq!(
+        book.concentration(Side::Buy),
+        Some(Decimal::from_str("0.25")?)
+    );
+    assert_eq!(book.concentration(Side::Sell), Some(Decimal::ONE));
+
+    let skewed = parse_order_book("BIDS:100.0,30|99.5,10;ASKS:101.0,7", None)?;
+    assert_eq!(
+        skewed.concentration(Side::Buy),
+        Some(Decimal::from_str("0.625")?)
+    );
+
+    let one_sided = parse_order_book("BIDS:100.0,10", None)?;
+    assert_eq!(one_sided.concentration(Side::Sell), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_parse_order_book_multi() -> Result<()> {
+    let input = "\nBIDS:100.0,10;ASKS:101.0,5\n\n\nBIDS:100.5,3;\nASKS:101.0,4\n\nASKS:102.0,1\n\n";
+    let books = parse_order_book_multi(input)?;
+
+    assert_eq!(books.len(), 3);
+    assert_eq!(books[0].bids[0].price, Decimal::from(100));
+    assert_eq!(books[1].asks[0].quantity, Decimal::from(4));
+    assert!(books[2].bids.is_empty());
+
+    let err = parse_order_book_multi("BIDS:100.0,10;ASKS:101.0,5\n\nBIDS:102.0,1;ASKS:101.0,5")
+        .unwrap_err();
+    assert!(matches!(
+        &err,
+        OrderBookError::SnapshotError(1, inner) if matches!(**inner, OrderBookError::CrossedBook(_, _))
+    ));
+    assert!(err.to_string().starts_with("Snapshot #1 failed"));
+
+    assert!(parse_order_book_multi("\n  \n")?.is_empty());
+
+    Ok(())
+}
+
+#[test]
+fn test_price_at_cumulative_qty() -> Result<()> {
+    let mut book = parse_order_book("BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10|103.0,5", None)?;
+
+    assert_eq!(
+        book.price_at_cumulative_qty(Side::Buy, Decimal::from(5)),
+        Some(Decimal::from(101))
+    );
+    assert_eq!(
+        book.price_at_cumulative_qty(Side::Buy, Decimal::from(6)),
+        Some(Decimal::from(102))
+    );
+    assert_eq!(
+        book.price_at_cumulative_qty(Side::Sell, Decimal::from(30)),
+        Some(Decimal::from_str("99.5")?)
+    );
+    assert_eq!(
+        book.price_at_cumulative_qty(Side::Sell, Decimal::from(31)),
+        None
+    );
+    assert_eq!(book.price_at_cumulative_qty(Side::Buy, Decimal::ZERO), None);
+
+    // Non-tradeable levels do not count towards the target.
+    book.asks[1].tradeable = false;
+    assert_eq!(
+        book.price_at_cumulative_qty(Side::Buy, Decimal::from(6)),
+        Some(Decimal::from(103))
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_trailing_data_is_rejected() -> Result<()> {
+    assert!(matches!(
+        parse_order_book("BIDS:100,1;ASKS:101,1;EXTRA", None),
+        Err(OrderBookError::TrailingData(rest)) if rest == ";EXTRA"
+    ));
+    assert!(matches!(
+        parse_order_book("BIDS:100,1;ASKS:101,1|CRC=1 garbage", None),
+        Err(OrderBookError::TrailingData(rest)) if rest == " garbage"
+    ));
+    assert!(matches!(
+        parse_order_book("BIDS:100,1 junk", None),
+        Err(OrderBookError::TrailingData(rest)) if rest == "junk"
+    ));
+    assert!(matches!(
+        parse_order_book("ASKS:101,1|CRC=1;BIDS:100,1", None),
+        Err(OrderBookError::TrailingData(rest)) if rest == ";BIDS:100,1"
+    ));
+
+    assert!(matches!(
+        parse_order_book_fast("BIDS:100,1;ASKS:101,1;EXTRA"),
+        Err(OrderBookError::TrailingData(_))
+    ));
+
+    // Trailing whitespace is not data.
+    assert!(parse_order_book("BIDS:100,1;ASKS:101,1 \n\n", None).is_ok());
+
+    Ok(())
+}
+
+#[test]
+fn test_strict_whitespace() -> Result<()> {
+    let options = ParseOptions {
+        strict_whitespace: true,
+        ..ParseOptions::default()
+    };
+
+    // Lenient by default.
+    assert!(parse_order_book("BIDS : 100 , 5 ; ASKS : 102 , 5", None).is_ok());
+
+    assert!(matches!(
+        parse_order_book_with_options("BIDS:100 ,5;ASKS:102,5", None, &options),
+        Err(OrderBookError::UnexpectedWhitespace(8))
+    ));
+    assert!(matches!(
+        parse_order_book_with_options("  BIDS:100,5;ASKS: 102,5", None, &options),
+        Err(OrderBookError::UnexpectedWhitespace(18))
+    ));
+
+    // Whitespace between sections and around the snapshot is fine.
+    let book =
+        parse_order_book_with_options("\n BIDS:100,5|99,1 ;\nASKS:102,5 \n", None, &options)?;
+    assert_eq!(book.bids.len(), 2);
+
+    Ok(())
+}
+
+#[test]
+fn test_liquidation_pnl_includes_slippage() -> Result<()> {
+    let book = parse_order_book("BIDS:100.0,10|99.0,10;ASKS:101.0,5|103.0,10", None)?;
+
+    let long = Position {
+        side: Side::Buy,
+        quantity: Decimal::from(20),
+        entry_price: Decimal::from(98),
+    };
+    // Exit VWAP is 99.5 across both bid levels, not the 100 touch.
+    assert_eq!(long.calculate_pnl(&book), Some(Decimal::from(40)));
+    assert_eq!(long.liquidation_pnl(&book), Some(Decimal::from(30)));
+
+    let short = Position {
+        side: Side::Sell,
+        quantity: Decimal::from(10),
+        entry_price: Decimal::from(104),
+    };
+    assert_eq!(short.liquidation_pnl(&book), Some(Decimal::from(20)));
+
+    let too_large = Position {
+        quantity: Decimal::from(21),
+        ..long
+    };
+    assert_eq!(too_large.liquidation_pnl(&book), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_percent_quantities() -> Result<()> {
+    let options = ParseOptions {
+        quantity_format: QuantityFormat::Percent,
+        ..ParseOptions::default()
+    };
+    let mut book =
+        parse_order_book_with_options("BIDS:100.0,25%|99.5,15%;ASKS:101.0,60%", None, &options)?;
+    assert_eq!(book.bids[0].quantity, Decimal::from_str("0.25")?);
+
+    book.resolve_percent_quantities(Decimal::from(400));
+    assert_eq!(book.bids[0].quantity, Decimal::from(100));
+    assert_eq!(book.bids[1].quantity, Decimal::from(60));
+    assert_eq!(book.asks[0].quantity, Decimal::from(240));
+    book.validate_instrument(&InstrumentConfig::new(0.5, 1.0, 1.0))?;
+
+    // Default behavior is unchanged and rejects the suffix.
+    assert!(matches!(
+        parse_order_book("BIDS:100.0,25%;ASKS:101.0,60", None),
+        Err(OrderBookError::InvalidQuantityFormat(_))
+    ));
+    assert!(matches!(
+        parse_order_book_fast("BIDS:100.0,25%;ASKS:101.0,60"),
+        Err(OrderBookError::InvalidQuantityFormat(_))
+    ));
+    // In percent mode every quantity needs the suffix, and prices never take it.
+    assert!(matches!(
+        parse_order_book_with_options("BIDS:100.0,25%;ASKS:101.0,60", None, &options),
+        Err(OrderBookError::InvalidQuantityFormat(_))
+    ));
+    assert!(matches!(
+        parse_order_book_with_options("BIDS:100%,25%;ASKS:101.0,60%", None, &options),
+        Err(OrderBookError::InvalidQuantityFormat(_))
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_top_of_book() -> Result<()> {
+    let book = parse_order_book("BIDS:100.0,10|99.5,20;ASKS:101.0,5|102.0,10", None)?;
+    assert_eq!(
+        book.top_of_book(),
+        Some(TopOfBook {
+            bid_price: Decimal::from(100),
+            bid_qty: Decimal::from(10),
+            ask_price: Decimal::from(101),
+            ask_qty: Decimal::from(5),
+        })
+    );
+
+    let one_sided = parse_order_book("BIDS:100.0,10", None)?;
+    assert_eq!(one_sided.top_of_book(), None);
+
+    Ok(())
+}
+
+#[test]
+fn test_exact_fill_stops_at_matching_level() -> Result<()> {
+    // Equal prices are rejected by validation; build the book directly to
+    // check the fill loop does not spill into a same-priced level.
+    let mut book = OrderBook {
+        bids: vec![Level::new_unchecked(Decimal::from(100), Decimal::from(10))],
+        asks: vec![
+            Level::new_unchecked(Decimal::from(101), Decimal::from(5)),
+            Level::new_unchecked(Decimal::from(101), Decimal::from(7)),
+            Level::new_unchecked(Decimal::from(102), Decimal::from(10)),
+        ],
+        ..OrderBook::default()
+    };
+
+    // The read-only simulation and the real fill agree.
+    let impact = book.price_impact(Side::Buy, Decimal::from(5));
+    assert_eq!(impact, Some(Decimal::ZERO));
+
+    let position = book.execute_market_order(Side::Buy, Decimal::from(5))?;
+    assert_eq!(position.quantity, Decimal::from(5));
+    assert_eq!(position.entry_price, Decimal::from(101));
+    assert_eq!(book.asks.len(), 2);
+    assert_eq!(book.asks[0].price, Decimal::from(101));
+    assert_eq!(book.asks[0].quantity, Decimal::from(7));
+
+    // Consuming exactly the next level removes it and leaves 102 untouched.
+    book.execute_market_order(Side::Buy, Decimal::from(7))?;
+    assert_eq!(
+        book.asks,
+        vec![Level::new_unchecked(Decimal::from(102), Decimal::from(10))]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_liquidity_saturates_instead_of_overflowing() -> Result<()> {
+    let mut book = parse_order_book(
+        "ASKS:1,79228162514264337593543950335|2,79228162514264337593543950335",
+        None,
+    )?;
+    assert_eq!(book.liquidity(Side::Buy), Decimal::MAX);
+
+    let position = book.execute_market_order(Side::Buy, Decimal::ONE)?;
+    assert_eq!(position.entry_price, Decimal::ONE);
+    assert!(matches!(
+        book.execute_market_order(Side::Sell, Decimal::ONE),
+        Err(OrderBookError::NotEnoughLiquidity(_, available)) if available.is_zero()
+    ));
+
+    Ok(())
+}
+
+#[test]
+fn test_position_aggregation_overflow() -> Result<()> {
+    let book = parse_order_book(
+        "BIDS:79228162514264337593543950334,2;ASKS:79228162514264337593543950335,1",
+        None,
+    )?;
+    let huge = Position {
+        side: Side::Buy,
+        quantity: Decimal::from(2),
+        entry_price: Decimal::MAX,
+    };
+
+    assert!(matches!(huge.merge(&huge), Err(OrderBookError::Overflow)));
+    assert_eq!(huge.calculate_pnl_bps(&book), None);
+    assert_eq!(book.fair_value(1), None);
+
+    let short = Position {
+        side: Side::Sell,
+        quantity: Decimal::from(3),
+        entry_price: Decimal::ONE,
+    };
+    assert_eq!(short.calculate_pnl(&book), None);
+    let mut portfolio = Portfolio::new();
+    portfolio.add(short);
+    assert!(matches!(
+        portfolio.total_pnl(&book),
+        Err(OrderBookError::Overflow)
+    ));
+
+    // Price math on the touch overflows into `None` / the fallback.
+    assert_eq!(book.mid_price(), None);
+    assert_eq!(book.spread_bps(), None);
+    assert_eq!(book.reference_price(Decimal::ONE), Decimal::ONE);
+    assert_eq!(book.depth_within_pct(Decimal::ONE), None);
+
+    // Quantity sums saturate, or report `Overflow` when they mutate the book.
+    let max = "79228162514264337593543950335";
+    let mut deep = parse_order_book(&format!("BIDS:2,{max}|1,{max};ASKS:3,{max}|4,{max}"), None)?;
+    assert_eq!(deep.cumulative(Side::Buy)[1].2, Decimal::MAX);
+    assert_eq!(
+        deep.liquidity_between(Side::Buy, Decimal::ZERO, Decimal::TEN),
+        Decimal::MAX
+    );
+    assert_eq!(
+        deep.quantity_to_price(Side::Buy, Decimal::from(4)),
+        Decimal::MAX
+    );
+    assert_eq!(deep.depth_within_pct(Decimal::MAX), None);
+
+    let before = deep.clone();
+    assert!(matches!(
+        deep.place_resting(Side::Buy, Decimal::TWO, Decimal::ONE),
+        Err(OrderBookError::Overflow)
+    ));
+    assert_eq!(deep, before);
+    assert!(
+        deep.match_incoming(Side::Buy, Decimal::TWO, Decimal::ONE)
+            .is_empty()
+    );
+    assert_eq!(deep.bids[0].quantity, Decimal::MAX);
+
+    let cheap = parse_order_book(&format!("BIDS:0.05,1;ASKS:0.1,{max}|0.2,{max}"), None)?;
+    assert_eq!(cheap.fill_for_notional(Side::Buy, Decimal::MAX), None);
+
+    let long = Position {
+        side: Side::Buy,
+        quantity: Decimal::MAX,
+        entry_price: Decimal::ONE,
+    };
+    let mut longs = Portfolio::new();
+    longs.add(long.clone());
+    longs.add(long);
+    assert_eq!(longs.net_exposure(), Decimal::MAX);
+
+    Ok(())
+}
+
+/// Book whose touch levels on both sides are indicative: the tradeable touch
+/// is 99 / 102.
+fn indicative_touch_book() -> Result<OrderBook> {
+    let mut book = parse_order_book("BIDS:100,10|99,10|98,10;ASKS:101,10|102,10|104,10", None)?;
+    book.bids[0].tradeable = false;
+    book.asks[0].tradeable = false;
+    Ok(book)
+}
+
+#[test]
+fn test_analytics_skip_non_tradeable_levels() -> Result<()> {
+    let mut book = indicative_touch_book()?;
+    let mid = book.mid_price().unwrap();
+    assert_eq!(mid, Decimal::new(1005, 1));
+
+    assert_eq!(book.vwap_top(Side::Buy, 1), Some(Decimal::from(99)));
+    assert_eq!(book.vwap_top(Side::Sell, 2), Some(Decimal::from(103)));
+    assert_eq!(book.fair_value(1), Some(mid));
+    assert_eq!(book.decay_weighted_mid(Decimal::ZERO), Some(mid));
+    assert_eq!(book.concentration(Side::Buy), Some(Decimal::new(5, 1)));
+    // Points (10, 0) and (20, 2) from the tradeable asks 102 and 104.
+    assert_eq!(book.kyle_lambda(Side::Buy, 2), Some(Decimal::new(2, 1)));
+
+    book.bids[0].timestamp = Some(0);
+    assert_eq!(book.weighted_age(100), None);
+    book.bids[1].timestamp = Some(90);
+    assert_eq!(book.weighted_age(100), Some(Decimal::from(10)));
+
+    Ok(())
+}
+
+#[test]
+fn test_depth_queries_skip_non_tradeable_levels() -> Result<()> {
+    let book = indicative_touch_book()?;
+
+    assert_eq!(
+        book.level_at(Side::Buy, 0).unwrap().price,
+        Decimal::from(99)
+    );
+    assert_eq!(
+        book.level_at(Side::Sell, 1).unwrap().price,
+        Decimal::from(104)
+    );
+    assert!(book.level_at(Side::Sell, 2).is_none());
+
+    let between = book.liquidity_between(Side::Buy, Decimal::from(99), Decimal::from(100));
+    assert_eq!(between, Decimal::from(10));
+    assert_eq!(
+        book.depth_within_pct(Decimal::TWO),
+        Some((Decimal::from(10), Decimal::from(10)))
+    );
+    assert_eq!(
+        book.cumulative(Side::Sell),
+        vec![
+            (Decimal::from(102), Decimal::from(10), Decimal::from(10)),
+            (Decimal::from(104), Decimal::from(10), Decimal::from(20)),
+        ]
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_touch_checks_skip_non_tradeable_levels() -> Result<()> {
+    let indicative = |price: i64| Level {
+        tradeable: false,
+        ..Level::new_unchecked(Decimal::from(price), Decimal::from(5))
+    };
+
+    // Indicative quotes crossing each other do not make the book crossed.
+    let mut book = parse_order_book("BIDS:100,3;ASKS:102,10", None)?;
+    book.bids.insert(0, indicative(103));
+    book.asks.insert(0, indicative(101));
+    book.validate()?;
+    assert_eq!(book.uncross(), 0);
+
+    // A locked tradeable touch is reported and repaired, leaving the
+    // indicative levels in place.
+    book.bids[1].price = Decimal::from(102);
+    assert!(matches!(
+        book.validate(),
+        Err(OrderBookError::LockedBook(_))
+    ));
+    assert_eq!(book.uncross(), 1);
+    assert_eq!(book.bids.len(), 1);
+    assert!(!book.bids[0].tradeable);
+    assert_eq!(book.asks.len(), 2);
+    book.validate()?;
+
+    Ok(())
+}